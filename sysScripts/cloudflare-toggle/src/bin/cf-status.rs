@@ -2,12 +2,36 @@
 //!
 //! A read-only utility to poll the status of the Cloudflare DNS service.
 //! Used by Waybar's `custom/script` module to display the current state.
+//!
+//! Besides the unit state it sends a real DNS query to the local proxy, so the module
+//! reports one of three states:
+//! - **on:** the service is active and answered within `probe_slow_ms`.
+//! - **degraded:** the service is active but the query failed, timed out or was slow.
+//! - **off:** the service is stopped.
+//!
+//! When a named profile was chosen with `cf-toggle --pick`, its text/class from
+//! `[cloudflare_toggle.profiles.<name>]` are shown instead of the on state, and its first
+//! nameserver (from the system profile file) is the one probed.
+//!
+//! While `cf-toggle --watch` has fallen back to the network's DNS for a captive portal, the
+//! `captive-portal` style is used (or the off state if there is none).
+//!
+//! If `metrics_endpoint` is set, cloudflared's Prometheus metrics are scraped too and its
+//! upstreams, request/error counts and p50/p99 request time are added to the tooltip.
 
+use std::collections::BTreeMap;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::process::Command;
+use std::time::Duration;
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use serde_json::json;
+use cloudflare_toggle::{metrics, probe};
+use cloudflare_toggle::profile::{self, Profile, PORTAL_PROFILE};
+
+/// The toggle unit assumed when the system profile file can't be read.
+const DEFAULT_UNIT: &str = "cloudflared-dns";
 
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
@@ -16,12 +40,40 @@ struct Config {
     class_on: String,
     text_off: String,
     class_off: String,
-    resolv_content_on: String,
-    resolv_content_off: String,
     bar_process_name: String,
     bar_signal_num: i32,
+    // Health probe
+    #[serde(default = "default_text_degraded")]
+    text_degraded: String,
+    #[serde(default = "default_class_degraded")]
+    class_degraded: String,
+    #[serde(default = "default_probe_server")]
+    probe_server: String,        // Local resolver to query, e.g. "127.0.0.1:53"
+    #[serde(default = "default_probe_hostname")]
+    probe_hostname: String,      // Name to resolve on every poll
+    #[serde(default = "default_probe_timeout_ms")]
+    probe_timeout_ms: u64,       // Give up (degraded) after this long
+    #[serde(default = "default_probe_slow_ms")]
+    probe_slow_ms: u64,          // Answers slower than this also count as degraded
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileStyle>,
+    metrics_endpoint: Option<String>, // cloudflared's `metrics:` address, e.g. "127.0.0.1:49312"
 }
 
+/// How a profile from /etc/cf-toggle/profiles.toml looks in the bar.
+#[derive(Deserialize, Debug)]
+struct ProfileStyle {
+    text: String,
+    class: String,
+}
+
+fn default_text_degraded() -> String { "DEGRADED".to_string() }
+fn default_class_degraded() -> String { "degraded".to_string() }
+fn default_probe_server() -> String { "127.0.0.1:53".to_string() }
+fn default_probe_hostname() -> String { "cloudflare.com".to_string() }
+fn default_probe_timeout_ms() -> u64 { 1500 }
+fn default_probe_slow_ms() -> u64 { 500 }
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     cloudflare_toggle: Config,
@@ -38,37 +90,152 @@ fn load_config() -> Result<GlobalConfig> {
     Ok(config)
 }
 
-fn main() -> Result<()> {
-    let config = load_config().map(|gc| gc.cloudflare_toggle);
-    
-    // 1. Check Service State
+enum Health {
+    Healthy(Option<Duration>), // None when there was nothing to probe (network default)
+    Degraded(String),
+    Off,
+    Portal, // Temporarily on the DHCP resolvers for a captive portal
+}
+
+fn unit_active(unit: &str) -> bool {
     // systemctl is-active returns "active" (exit code 0) or "inactive" (exit code 3/4).
-    let service_active = Command::new("systemctl")
+    Command::new("systemctl")
         .arg("is-active")
-        .arg("cloudflared-dns")
+        .arg(unit)
         .output()
         .map(|o| o.status.success())
-        .unwrap_or(false);
-    // 2. Read DNS Configuration
+        .unwrap_or(false)
+}
+
+/// Sends one real query to `server` (an `ip:port`, or a bare IP for port 53).
+/// An active unit is not proof of working DNS, so I ask it for a real answer.
+fn probe_health(server: &str, config: Option<&Config>) -> Health {
+    let hostname = config.map_or_else(default_probe_hostname, |c| c.probe_hostname.clone());
+    let timeout = Duration::from_millis(config.map_or_else(default_probe_timeout_ms, |c| c.probe_timeout_ms));
+    let slow = Duration::from_millis(config.map_or_else(default_probe_slow_ms, |c| c.probe_slow_ms));
+
+    let addr = server.parse::<SocketAddr>()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)));
+    let Ok(addr) = addr else {
+        return Health::Degraded(format!("Invalid resolver address '{}'", server));
+    };
+    match probe::query(addr, &hostname, timeout) {
+        Ok(rtt) if rtt > slow => Health::Degraded(format!("Slow: {} took {} ms", hostname, rtt.as_millis())),
+        Ok(rtt) => Health::Healthy(Some(rtt)),
+        Err(e) => Health::Degraded(format!("{}: {:#}", hostname, e)),
+    }
+}
+
+/// Health of a named profile: its unit must be up and its first nameserver must answer.
+fn profile_health(profile: &Profile, config: Option<&Config>) -> Health {
+    if let Some(unit) = &profile.unit
+        && !unit_active(unit)
+    {
+        return Health::Degraded(format!("{} is not running", unit));
+    }
+    match profile.nameservers.first() {
+        Some(server) => probe_health(&server.to_string(), config),
+        None => Health::Healthy(None),
+    }
+}
+
+/// Tooltip lines from cloudflared's metrics. A missing or unreachable endpoint is one line, not an error.
+fn metrics_summary(endpoint: &str, config: &Config) -> String {
+    let timeout = Duration::from_millis(config.probe_timeout_ms);
+    let stats = match metrics::scrape(endpoint, timeout) {
+        Ok(stats) => stats,
+        Err(e) => return format!("Metrics: unavailable ({:#})", e),
+    };
+    let count = |n: Option<u64>| n.map_or_else(|| "n/a".to_string(), |n| n.to_string());
+    let ms = |d: Option<Duration>| d.map_or_else(|| "n/a".to_string(), |d| format!("{} ms", d.as_millis()));
+    let upstreams = if stats.upstreams.is_empty() { "n/a".to_string() } else { stats.upstreams.join(", ") };
+    format!(
+        "Upstream: {}\nRequests: {} (errors: {})\nRequest time: p50 {}, p99 {}",
+        upstreams, count(stats.requests), count(stats.errors), ms(stats.p50), ms(stats.p99),
+    )
+}
+
+fn main() -> Result<()> {
+    let config = load_config().map(|gc| gc.cloudflare_toggle);
+
+    // 1. Check Active Profile & Service State
+    // A profile picked in cf-toggle --pick wins; otherwise it's the plain cloudflared on/off.
+    let cfg = config.as_ref().ok();
+    let system = profile::load_system().ok();
+    let active_name = profile::active_profile();
+    let in_portal = active_name.as_deref() == Some(PORTAL_PROFILE);
+    let active_profile = active_name.and_then(|name| {
+        let p = system.as_ref()?.profiles.get(&name)?;
+        Some((name, p))
+    });
+    let toggle_unit = system.as_ref().map_or(DEFAULT_UNIT, |s| s.toggle.unit.as_str());
+
+    // 2. Probe The Resolver
+    let health = match &active_profile {
+        _ if in_portal => Health::Portal,
+        Some((_, p)) => profile_health(p, cfg),
+        None if !unit_active(toggle_unit) => Health::Off,
+        None => probe_health(&cfg.map_or_else(default_probe_server, |c| c.probe_server.clone()), cfg),
+    };
+
+    // 3. Read DNS Configuration
     // We display the actual content of resolv.conf in the tooltip for verification.
     let resolv_conf = fs::read_to_string("/etc/resolv.conf")
         .unwrap_or_else(|_| "Error reading /etc/resolv.conf".to_string());
+    let header = match &active_profile {
+        Some((name, _)) => format!("Profile {}", name),
+        None => toggle_unit.to_string(),
+    };
+
+    // 4. Scrape cloudflared (only while it is the unit serving DNS)
+    let serving_cloudflared = match &active_profile {
+        Some((_, p)) => p.unit.as_deref() == Some(toggle_unit),
+        None => true,
+    };
+    let metrics = match (&health, cfg) {
+        (Health::Healthy(_) | Health::Degraded(_), Some(c)) if serving_cloudflared => c.metrics_endpoint
+            .as_deref()
+            .map(|endpoint| format!("\n{}", metrics_summary(endpoint, c))),
+        _ => None,
+    }.unwrap_or_default();
 
-    // 3. Determine UI State
-    let (text, class, tooltip) = if service_active {
-        (
-            config.as_ref().map_or("ON", |c| &c.text_on),
-            config.as_ref().map_or("on", |c| &c.class_on),
-            format!("Cloudflared:Running\nresolv.conf: {}", resolv_conf.trim())
-        )
-    } else {
-        (
+    // 5. Determine UI State
+    let (text, class, tooltip) = match &health {
+        Health::Healthy(rtt) => {
+            let style = active_profile.as_ref()
+                .and_then(|(name, _)| cfg?.profiles.get(name));
+            let (text, class) = match (style, cfg) {
+                (Some(style), _) => (style.text.as_str(), style.class.as_str()),
+                (None, Some(c)) => (c.text_on.as_str(), c.class_on.as_str()),
+                (None, None) => ("ON", "on"),
+            };
+            let latency = rtt.map_or_else(
+                || "Latency: n/a (network default)".to_string(),
+                |rtt| format!("Latency: {} ms", rtt.as_millis()),
+            );
+            (text, class, format!("{}: Running\n{}{}\nresolv.conf: {}", header, latency, metrics, resolv_conf.trim()))
+        }
+        Health::Degraded(reason) => (
+            config.as_ref().map_or("DEGRADED", |c| &c.text_degraded),
+            config.as_ref().map_or("degraded", |c| &c.class_degraded),
+            format!("{}: Degraded\n{}{}\nresolv.conf: {}", header, reason, metrics, resolv_conf.trim())
+        ),
+        Health::Off => (
             config.as_ref().map_or("OFF", |c| &c.text_off),
             config.as_ref().map_or("off", |c| &c.class_off),
-            format!("Cloudflared: Stopped\nresolv.conf: {}", resolv_conf.trim())
-        )
+            format!("{}: Stopped\nresolv.conf: {}", header, resolv_conf.trim())
+        ),
+        Health::Portal => {
+            let style = cfg.and_then(|c| c.profiles.get(PORTAL_PROFILE));
+            let (text, class) = match (style, cfg) {
+                (Some(style), _) => (style.text.as_str(), style.class.as_str()),
+                (None, Some(c)) => (c.text_off.as_str(), c.class_off.as_str()),
+                (None, None) => ("OFF", "off"),
+            };
+            (text, class, format!("Captive portal detected: using the network's DNS\nEncrypted DNS returns once you are logged in\nresolv.conf: {}", resolv_conf.trim()))
+        }
     };
-    // 4. Output JSON
+    // 6. Output JSON
     println!("{}", json!({
         "text": text,
         "class": class,
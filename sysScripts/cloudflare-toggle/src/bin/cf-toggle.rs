@@ -3,18 +3,52 @@
 //! A secure wrapper for toggling system-level DNS-over-HTTPS settings.
 //!
 //! Architecture:
-//! 1. **User Mode:** When run by a normal user (e.g., clicking Waybar), it detects the current state 
+//! 1. **User Mode:** When run by a normal user (e.g., clicking Waybar), it detects the current state
 //!    and re-executes *itself* using `pkexec` to gain root privileges.
-//! 2. **Root Mode:** When executed with root privileges (via pkexec), it modifies `/etc/resolv.conf`
+//! 2. **Root Mode:** When executed with root privileges (via pkexec), it repoints the system
+//!    resolver (see `cloudflare_toggle::dns` for the resolved / resolv.conf backends)
 //!    and manages the `systemd` service.
 //!
+//! Usage:
+//!   cf-toggle          => Toggle cloudflared on/off.
+//!   cf-toggle --pick   => Choose one of the profiles in rofi.
+//!   cf-toggle --watch  => Captive-portal watcher (run at login, see below).
+//!
 //! This design avoids needing `sudo` in scripts or storing passwords.
+//!
+//! Hardening: pkexec always runs the root-owned copy at `HELPER_PATH`, which has its own
+//! polkit action (`system/io.github.mccalabrese.cf-toggle.policy`). The root half accepts
+//! exactly `--start`, `--stop`, `--profile <name>` or `--portal`; nameservers and units come
+//! from the root-owned `/etc/cf-toggle/profiles.toml` (or NetworkManager, for `--portal`),
+//! never from argv. Every privileged change
+//! (and every refusal) is logged to the journal under the `cf-toggle` tag.
+//!
+//! Captive portals: `--watch` re-checks connectivity on every network change. When a portal
+//! intercepts the check, it escalates `--portal`, which points DNS at the resolvers DHCP
+//! handed out so the login page can load. Once the check comes back clean it switches back
+//! to whatever was active before (`--start` or `--profile <name>`).
 
 use std::env;
 use std::fs;
-use std::process::Command;
+use std::io::Write;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 use anyhow::{Context, Result};
-use serde::Deserialize; 
+use notify_rust::Notification;
+use serde::Deserialize;
+use cloudflare_toggle::dns::Backend;
+use cloudflare_toggle::portal::{self, Connectivity};
+use cloudflare_toggle::profile::{self, PORTAL_PROFILE, PROFILES_FILE};
+
+/// The root-owned copy of this binary that pkexec is allowed to run (see the polkit action).
+/// The user's `~/.cargo/bin` copy is writable by the user, so it must never be the one elevated.
+const HELPER_PATH: &str = "/usr/local/bin/cf-toggle";
+/// The unit checked by the plain toggle if the profile file can't be read.
+const DEFAULT_UNIT: &str = "cloudflared-dns";
 
 // --- Configuration ---
 // Deserialize the full config struct even if we don't use all fields in this binary,
@@ -28,12 +62,22 @@ struct Config {
     text_off: String,
     class_off: String,
     // Logic fields (Used by cf-toggle)
-    resolv_content_on: String,   // e.g. "nameserver 127.0.0.1"
-    resolv_content_off: String,  // e.g. "nameserver 1.1.1.1"
     bar_process_name: String,    // "waybar"
     bar_signal_num: i32,         // Signal offset
+    rofi_config: Option<String>, // Theme for the --pick menu
+    // Captive-portal watcher (--watch)
+    #[serde(default = "default_portal_check_url")]
+    portal_check_url: String,    // Plain-HTTP page a portal will intercept
+    #[serde(default = "default_portal_check_expect")]
+    portal_check_expect: String, // Body the real page returns
+    #[serde(default = "default_portal_poll_secs")]
+    portal_poll_secs: u64,       // Re-check this often even without network events
 }
 
+fn default_portal_check_url() -> String { "http://nmcheck.gnome.org/check_network_status.txt".to_string() }
+fn default_portal_check_expect() -> String { "NetworkManager is online".to_string() }
+fn default_portal_poll_secs() -> u64 { 60 }
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     cloudflare_toggle: Config,
@@ -50,8 +94,50 @@ fn load_config() -> Result<GlobalConfig> {
     Ok(config)
 }
 
+/// Expands the tilde (`~`) in file paths to the user's home directory.
+fn expand_path(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
+    }
+    PathBuf::from(path)
+}
+
 // --- User Mode (Phase 1) ---
 
+/// Runs the root-owned helper through pkexec with `args`. Returns true if it succeeded.
+fn escalate(args: &[&str]) -> Result<bool> {
+    if !Path::new(HELPER_PATH).exists() {
+        anyhow::bail!("{} is missing. Install it as root: sudo install -m 755 ~/.cargo/bin/cf-toggle {}", HELPER_PATH, HELPER_PATH);
+    }
+    let status = Command::new("pkexec")
+        .arg(HELPER_PATH)
+        .args(args)
+        .status()
+        .context("Failed to run pkexec")?;
+    Ok(status.success())
+}
+
+/// Signal Waybar to refresh status immediately.
+fn refresh_bar(config: &Config) {
+    let sig_base = 34;
+    let signal = sig_base + config.bar_signal_num;
+    let _ = Command::new("pkill")
+        .arg(format!("-{}", signal))
+        .arg("-x")
+        .arg(&config.bar_process_name)
+        .status();
+}
+
+fn notify(summary: &str, body: &str) {
+    let _ = Notification::new()
+        .summary(summary)
+        .body(body)
+        .icon("network-wireless")
+        .show();
+}
+
 /// The entry point for the standard user.
 /// Determines the desired state change and requests Root access to perform it.
 fn run_as_user() -> Result<()> {
@@ -60,107 +146,372 @@ fn run_as_user() -> Result<()> {
         .cloudflare_toggle;
 
     // Check current service status to toggle it
+    let unit = profile::load_system()
+        .map(|system| system.toggle.unit)
+        .unwrap_or_else(|_| DEFAULT_UNIT.to_string());
     let is_running = Command::new("systemctl")
         .arg("is-active")
-        .arg("cloudflared-dns")
+        .arg(&unit)
         .output()
         .map(|o| o.status.success())
         .unwrap_or(false);
-    
+
     let mode = if is_running { "--stop" } else { "--start" };
-    let content_on = &config.resolv_content_on;
-    let content_off = &config.resolv_content_off;
-    // Self-Reference: Find where this binary lives so we can execute it as root
-    let self_exe = env::current_exe()
-        .context("Failed to get path to own executable")?;
 
     // Privilege Escalation
-    // We pass the config values as arguments to the root process so the root process
-    // doesn't have to try and locate/read the user's home directory config file.
-    let status = Command::new("pkexec")
-        .arg(self_exe)
-        .arg(mode)
-        .arg(content_on)
-        .arg(content_off)
-        .status()
-        .context("Failed to run pkexec")?;
+    // Only the mode crosses the privilege boundary; the root half reads everything else
+    // from its own root-owned profile file.
+    if escalate(&[mode])? {
+        refresh_bar(&config);
+    }
+    Ok(())
+}
+
+/// Shows the profiles in rofi (the active one preselected) and switches to the chosen one.
+fn run_picker() -> Result<()> {
+    let config = load_config()
+        .context("Failed to load config for user")?
+        .cloudflare_toggle;
+    // The list comes from the system file: those are the only names the root half accepts.
+    let system = profile::load_system()?;
+    if system.profiles.is_empty() {
+        anyhow::bail!("No [profiles] configured in {}", PROFILES_FILE);
+    }
+
+    let names: Vec<&String> = system.profiles.keys().collect();
+    let active = profile::active_profile();
+    let selected_row = active.as_ref()
+        .and_then(|a| names.iter().position(|n| *n == a))
+        .unwrap_or(0);
+
+    let mut cmd = Command::new("rofi");
+    cmd.arg("-dmenu")
+        .arg("-i")
+        .arg("-p")
+        .arg("DNS")
+        .arg("-selected-row")
+        .arg(selected_row.to_string());
+    if let Some(rofi_config) = &config.rofi_config {
+        cmd.arg("-config").arg(expand_path(rofi_config));
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to launch rofi")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let list: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        stdin.write_all(list.join("\n").as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    // Rofi exits with 1 on Escape
+    if !output.status.success() {
+        return Ok(());
+    }
+
+    let choice = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !system.profiles.contains_key(&choice) || active.as_deref() == Some(choice.as_str()) {
+        return Ok(());
+    }
 
-    // Signal Waybar to refresh status immediately on success
-    if status.success() {
-        let sig_base = 34;
-        let signal = sig_base + config.bar_signal_num;
-        let _ = Command::new("pkill")
-            .arg(format!("-{}", signal))
-            .arg("-x")
-            .arg(&config.bar_process_name)
-            .status();
+    if escalate(&["--profile", &choice])? {
+        refresh_bar(&config);
     }
     Ok(())
 }
 
+/// True if the current state sends DNS somewhere a portal can't answer
+/// (the toggle is on, or the active profile has its own nameservers).
+fn encrypted_dns_active() -> bool {
+    let system = profile::load_system().ok();
+    match profile::active_profile() {
+        Some(name) => system
+            .and_then(|s| s.profiles.get(&name).map(|p| !p.nameservers.is_empty()))
+            .unwrap_or(false),
+        None => {
+            let unit = system.map_or_else(|| DEFAULT_UNIT.to_string(), |s| s.toggle.unit);
+            Command::new("systemctl")
+                .arg("is-active")
+                .arg(&unit)
+                .output()
+                .map(|o| o.status.success())
+                .unwrap_or(false)
+        }
+    }
+}
+
+/// Watches for network changes and falls back to DHCP DNS while a captive portal is in the way.
+fn run_watcher() -> Result<()> {
+    let config = load_config()
+        .context("Failed to load config for user")?
+        .cloudflare_toggle;
+    let poll = Duration::from_secs(config.portal_poll_secs.max(5));
+
+    // 1. Network Events
+    // `ip monitor` prints a line for every address/route change (new Wi-Fi, DHCP lease...).
+    // If it can't run, the periodic poll still covers us.
+    let (tx, rx) = mpsc::channel::<()>();
+    let monitor_tx = tx.clone();
+    thread::spawn(move || {
+        let child = Command::new("ip")
+            .args(["monitor", "address", "route"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            eprintln!("cf-toggle: ip monitor unavailable, polling only");
+            return;
+        };
+        if let Some(stdout) = child.stdout.take() {
+            for _ in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if monitor_tx.send(()).is_err() {
+                    break;
+                }
+            }
+        }
+        let _ = child.wait();
+    });
+    // Check once right away, as if the network had just come up.
+    let _ = tx.send(());
+    // The network on which a switch was declined (polkit dismissed) or failed. Not asked
+    // again until the connection changes, or every poll would bring the prompt back.
+    let mut declined: Option<String> = None;
+
+    loop {
+        // 2. Wait For A Change (or the poll interval)
+        match rx.recv_timeout(poll) {
+            Ok(()) => {
+                // A reconnect is a burst of events; let DHCP settle before probing.
+                thread::sleep(Duration::from_secs(3));
+                while rx.try_recv().is_ok() {}
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Network event channel closed"),
+        }
+
+        let in_fallback = profile::active_profile().as_deref() == Some(PORTAL_PROFILE);
+        if !in_fallback && !encrypted_dns_active() {
+            continue; // DNS is already plain; a portal will work without us.
+        }
+
+        // 3. Probe Through The Network's Own Resolvers
+        let resolvers = match portal::dhcp_resolvers() {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("cf-toggle: {:#}", e);
+                continue;
+            }
+        };
+        let state = match portal::check(&resolvers, &config.portal_check_url, &config.portal_check_expect) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("cf-toggle: {:#}", e);
+                continue;
+            }
+        };
+
+        // 4. Switch
+        let switch: Option<(Vec<String>, &str, String)> = match &state {
+            Connectivity::Portal(reason) if !in_fallback => {
+                println!("Captive portal: {}", reason);
+                Some((vec!["--portal".to_string()], "Captive portal detected", format!("{}\nUsing the network's DNS until you are logged in.", reason)))
+            }
+            Connectivity::Clean if in_fallback => {
+                let back = profile::portal_return();
+                let body = format!("Encrypted DNS is back on ({}).", back.as_deref().unwrap_or("toggle"));
+                let args = match back {
+                    Some(name) => vec!["--profile".to_string(), name],
+                    None => vec!["--start".to_string()],
+                };
+                Some((args, "Connectivity restored", body))
+            }
+            Connectivity::Offline(reason) => {
+                println!("Offline: {}", reason);
+                None
+            }
+            _ => None,
+        };
+        let Some((args, summary, body)) = switch else { continue };
+        let network = portal::network_id().unwrap_or_else(|e| {
+            eprintln!("cf-toggle: {:#}", e);
+            String::new()
+        });
+        if declined.as_ref() == Some(&network) {
+            continue;
+        }
+        // A missing helper or a failed pkexec is logged; the watcher keeps going.
+        match escalate(&args.iter().map(String::as_str).collect::<Vec<_>>()) {
+            Ok(true) => {
+                declined = None;
+                notify(summary, &body);
+                refresh_bar(&config);
+            }
+            Ok(false) => {
+                println!("Switch declined or failed; not asking again on this network");
+                declined = Some(network);
+            }
+            Err(e) => {
+                eprintln!("cf-toggle: {:#}", e);
+                declined = Some(network);
+            }
+        }
+    }
+}
+
 // --- Root Mode (Phase 2) ---
 
+/// The only requests the privileged half understands.
+enum RootMode {
+    Start,
+    Stop,
+    Profile(String),
+    Portal,
+}
+
+/// Strict parsing: exactly one known mode, with exactly the arguments it needs.
+fn parse_root_args(args: &[String]) -> Result<RootMode> {
+    match args {
+        [mode] if mode == "--start" => Ok(RootMode::Start),
+        [mode] if mode == "--stop" => Ok(RootMode::Stop),
+        [mode, name] if mode == "--profile" => Ok(RootMode::Profile(name.clone())),
+        [mode] if mode == "--portal" => Ok(RootMode::Portal),
+        _ => anyhow::bail!("Invalid arguments {:?}. The root helper accepts exactly one of: --start, --stop, --profile <name>, --portal.", args),
+    }
+}
+
+/// True if the effective UID is 0 (read from /proc to avoid pulling in libc for one call).
+fn is_root() -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|l| l.starts_with("Uid:"))?.to_string();
+            line.split_whitespace().nth(2).map(|euid| euid == "0")
+        })
+        .unwrap_or(false)
+}
+
+/// Writes an audit line to the journal (through `logger`), tagged with the user pkexec ran for.
+fn journal(priority: &str, message: &str) {
+    let caller = env::var("PKEXEC_UID").unwrap_or_else(|_| "unknown".to_string());
+    let line = format!("uid={} {}", caller, message);
+    eprintln!("{}", line);
+    let _ = Command::new("logger")
+        .arg("-t")
+        .arg("cf-toggle")
+        .arg("-p")
+        .arg(format!("authpriv.{}", priority))
+        .arg("--")
+        .arg(&line)
+        .status();
+}
+
+fn systemctl(action: &str, unit: &str) -> Result<()> {
+    Command::new("systemctl")
+        .arg(action)
+        .arg("--now")
+        .arg(unit)
+        .status()?
+        .success()
+        .then_some(())
+        .with_context(|| format!("Failed to {} {}", action, unit))
+}
+
+/// Where the resolver should point once the units are settled.
+#[derive(Clone, Copy)]
+enum DnsTarget<'a> {
+    Apply(&'a str),           // resolv.conf body to switch to
+    Restore(Option<&'a str>), // back to the original setup, with an optional fallback
+}
+
+/// Moves from whatever is recorded as active to the new state:
+/// stop the old unit, start the new one, repoint DNS, then record it.
+/// `portal_return` is only kept when entering the captive-portal fallback.
+fn switch_state(previous_unit: Option<String>, profile_name: Option<&str>, unit: Option<&str>, dns: DnsTarget, portal_return: Option<&str>) -> Result<()> {
+    let backend = Backend::detect();
+    println!("DNS backend: {}", backend.name());
+
+    // Stop the old service first so it can't hold the port the new one wants (both bind :53).
+    if let Some(previous) = previous_unit.as_deref().filter(|p| Some(*p) != unit) {
+        systemctl("disable", previous)?;
+    }
+    if let Some(unit) = unit {
+        systemctl("enable", unit)?;
+    }
+    match dns {
+        DnsTarget::Apply(resolv) => backend.apply(resolv)?,
+        DnsTarget::Restore(fallback) => backend.restore(fallback)?,
+    }
+    profile::record(profile_name, unit, portal_return).context("Failed to record active profile")?;
+    journal("notice", &format!(
+        "switched DNS to {} (unit: {}, backend: {}, resolvers: {})",
+        profile_name.unwrap_or(if unit.is_some() { "toggle on" } else { "toggle off" }),
+        unit.unwrap_or("none"),
+        backend.name(),
+        match dns {
+            DnsTarget::Apply(resolv) => resolv.split_whitespace().filter(|w| *w != "nameserver").collect::<Vec<_>>().join(" "),
+            DnsTarget::Restore(_) => "original".to_string(),
+        },
+    ));
+    Ok(())
+}
+
 /// The privileged worker.
 /// This function only runs when `pkexec` invokes this binary.
 /// It has permission to write to /etc/ and control systemd.
-fn run_as_root(mode: &str, content_on: &str, content_off: &str) -> Result<()> {
-    if mode == "--start" {
-        // Enable service
-        Command::new("systemctl")
-            .arg("enable")
-            .arg("--now")
-            .arg("cloudflared-dns")
-            .status()?
-            .success()
-            .then_some(())
-            .context("Failed to start systemctl service")?;
-
-        // Overwrite DNS
-        fs::write("/etc/resolv.conf", content_on)
-            .context("Failed to write /etc/resolv.conf")?;
-
-    } else if mode == "--stop" {
-        // Disable Service
-        Command::new("systemctl")
-            .arg("disable")
-            .arg("--now")
-            .arg("cloudflared-dns")
-            .status()?
-            .success()
-            .then_some(())
-            .context("Failed to stop systemctl service")?;
-        
-        // Restore DNS
-        fs::write("/etc/resolv.conf", content_off)
-            .context("Failed to write /etc/resolv.conf")?;
+fn run_as_root(mode: RootMode) -> Result<()> {
+    anyhow::ensure!(is_root(), "Root mode must be run through pkexec");
+    let system = profile::load_system()?;
+
+    match mode {
+        RootMode::Start => {
+            // Enable service & point DNS at the proxy (snapshotting whatever was there first)
+            let resolv = profile::resolv_content(&system.toggle.nameservers);
+            switch_state(profile::active_unit(), None, Some(&system.toggle.unit), DnsTarget::Apply(&resolv), None)
+        }
+        RootMode::Stop => {
+            // Disable Service & restore DNS exactly as it was before --start.
+            let previous = profile::unit_to_stop(profile::active_unit(), &system.toggle);
+            let fallback = profile::resolv_content(&system.toggle.fallback_nameservers);
+            let fallback = Some(fallback.as_str()).filter(|f| !f.is_empty());
+            switch_state(Some(previous), None, None, DnsTarget::Restore(fallback), None)
+        }
+        RootMode::Profile(name) => {
+            let chosen = system.profiles.get(&name)
+                .with_context(|| format!("Unknown profile '{}' (not in {})", name, PROFILES_FILE))?;
+            let resolv = profile::resolv_content(&chosen.nameservers);
+            // No nameservers = ISP/DHCP default, i.e. whatever was there before we touched it.
+            let dns = if resolv.is_empty() { DnsTarget::Restore(None) } else { DnsTarget::Apply(&resolv) };
+            switch_state(profile::active_unit(), Some(&name), chosen.unit.as_deref(), dns, None)
+        }
+        RootMode::Portal => {
+            let previous_profile = profile::active_profile();
+            anyhow::ensure!(previous_profile.as_deref() != Some(PORTAL_PROFILE), "Captive-portal fallback is already active");
+            // The resolvers come from NetworkManager (root-side), not from the caller.
+            let resolvers = portal::dhcp_resolvers()?;
+            anyhow::ensure!(!resolvers.is_empty(), "The network did not hand out any DNS servers");
+            let resolv = profile::resolv_content(&resolvers);
+            // Remember what to go back to; None means the plain toggle.
+            switch_state(profile::active_unit(), Some(PORTAL_PROFILE), None, DnsTarget::Apply(&resolv), previous_profile.as_deref())
+        }
     }
-    Ok(())
 }
 
 // --- Main Dispatcher ---
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    // Detect Mode based on arguments
-    // If arguments exist, we assume we are the child process running as Root.
-    if args.len() > 1 {
-        let mode = &args[1];
-        // Simple validation to ensure we are in the expected state
-        if mode != "--start" && mode != "--stop" {
-            if args.len() < 4 {
-                eprintln!("Internal Error: Missing arguments for root mode.");
-                return Ok(());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        // No arguments? We are the user clicking the button.
+        None => run_as_user(),
+        Some("--pick") if args.len() == 1 => run_picker(),
+        Some("--watch") if args.len() == 1 => run_watcher(),
+        // Anything else is a request for the privileged half, which must parse strictly.
+        Some(_) => {
+            let result = parse_root_args(&args).and_then(run_as_root);
+            if let Err(e) = &result {
+                journal("warning", &format!("refused or failed: {:#}", e));
             }
-            let content_on = &args[2];
-            let content_off = &args[3];
-            run_as_root(mode, content_on, content_off)
-        } else {
-            let content_on = args.get(2).context("Missing content_on")?;
-            let content_off = args.get(3).context("Missing content_off")?;
-            run_as_root(mode, content_on, content_off)
+            result
         }
-    } else {
-        // No arguments? We are the user clicking the button.
-        run_as_user()
     }
 }
@@ -0,0 +1,225 @@
+//! DNS Backends
+//!
+//! How the system resolver is pointed at (and away from) the local proxy depends on who
+//! owns `/etc/resolv.conf`:
+//!
+//! - **systemd-resolved:** resolv.conf is a symlink into `/run/systemd/resolve/`. Writing to it
+//!   would clobber resolved's stub, so I set per-link DNS with `resolvectl` on the links that
+//!   carry the default route and `resolvectl revert` them on stop.
+//! - **Plain resolv.conf:** the exact prior file is snapshotted (bytes, or the symlink target
+//!   if it was a link) before the first change, and restored as-is on stop.
+//!
+//! Both keep their state under `STATE_DIR` so a stop in a later invocation (or after a
+//! reboot, for the resolv.conf snapshot) knows what to undo.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::process::Command;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::{portal, profile};
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+pub const STATE_DIR: &str = "/var/lib/cf-toggle";
+
+const RESOLVED_RUNTIME_DIR: &str = "/run/systemd/resolve";
+const SNAPSHOT_FILE: &str = "resolv.conf.orig";     // Original bytes of a regular resolv.conf
+const SNAPSHOT_LINK: &str = "resolv.conf.link";     // Original symlink target
+const RESOLVED_LINKS: &str = "resolved-links";      // Links we changed via resolvectl
+
+pub enum Backend {
+    Resolved,
+    ResolvConf,
+}
+
+impl Backend {
+    /// Picks resolved only when it is running *and* resolv.conf actually points at it;
+    /// otherwise glibc would never see the per-link settings.
+    pub fn detect() -> Self {
+        let resolved_active = Command::new("systemctl")
+            .args(["is-active", "--quiet", "systemd-resolved"])
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        let points_at_resolved = fs::canonicalize(RESOLV_CONF)
+            .map(|target| target.starts_with(RESOLVED_RUNTIME_DIR))
+            .unwrap_or(false);
+        if resolved_active && points_at_resolved { Backend::Resolved } else { Backend::ResolvConf }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Resolved => "systemd-resolved",
+            Backend::ResolvConf => "resolv.conf",
+        }
+    }
+
+    /// Points the system at the resolvers in `resolv` (resolv.conf syntax;
+    /// the resolved backend only uses its `nameserver` lines).
+    pub fn apply(&self, resolv: &str) -> Result<()> {
+        fs::create_dir_all(STATE_DIR).with_context(|| format!("Failed to create {}", STATE_DIR))?;
+        match self {
+            Backend::Resolved => resolved_apply(&nameservers(resolv)),
+            Backend::ResolvConf => resolv_conf_apply(resolv),
+        }
+    }
+
+    /// Undoes `apply`. `fallback` is only written when there is no resolv.conf snapshot
+    /// to restore (e.g. DNS was switched on by an older version of this tool); without one,
+    /// the resolvers DHCP handed out are used, and failing that it is an error.
+    pub fn restore(&self, fallback: Option<&str>) -> Result<()> {
+        match self {
+            Backend::Resolved => resolved_restore(),
+            Backend::ResolvConf => resolv_conf_restore(fallback),
+        }
+    }
+}
+
+/// Extracts the addresses from `nameserver <addr>` lines.
+pub fn nameservers(resolv: &str) -> Vec<String> {
+    resolv.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?
+        .success()
+        .then_some(())
+        .with_context(|| format!("{} {} failed", program, args.join(" ")))
+}
+
+// --- systemd-resolved ---
+
+#[derive(Deserialize)]
+struct Route {
+    dev: String,
+}
+
+/// Interfaces that carry a default route (IPv4 or IPv6), i.e. the ones DNS goes out on.
+fn default_route_links() -> Result<Vec<String>> {
+    let mut links = Vec::new();
+    for family in ["-4", "-6"] {
+        let output = Command::new("ip")
+            .args([family, "-j", "route", "show", "default"])
+            .output()
+            .context("Failed to run ip")?;
+        let routes: Vec<Route> = serde_json::from_slice(&output.stdout).unwrap_or_default();
+        for route in routes {
+            if !links.contains(&route.dev) {
+                links.push(route.dev);
+            }
+        }
+    }
+    Ok(links)
+}
+
+fn resolved_apply(servers: &[String]) -> Result<()> {
+    anyhow::ensure!(!servers.is_empty(), "No nameserver lines to hand to systemd-resolved");
+    let links = default_route_links()?;
+    anyhow::ensure!(!links.is_empty(), "No network link with a default route");
+
+    for link in &links {
+        let mut args = vec!["dns", link.as_str()];
+        args.extend(servers.iter().map(String::as_str));
+        run("resolvectl", &args)?;
+        // "~." makes this link the route for every domain, so no other link's DNS leaks queries.
+        run("resolvectl", &["domain", link, "~."])?;
+    }
+    fs::write(Path::new(STATE_DIR).join(RESOLVED_LINKS), links.join("\n"))
+        .context("Failed to record changed links")?;
+    Ok(())
+}
+
+fn resolved_restore() -> Result<()> {
+    let record = Path::new(STATE_DIR).join(RESOLVED_LINKS);
+    let links: Vec<String> = match fs::read_to_string(&record) {
+        Ok(saved) => saved.lines().map(str::to_string).collect(),
+        // Nothing recorded: revert whatever carries traffic now, which is harmless if untouched.
+        Err(_) => default_route_links()?,
+    };
+    for link in &links {
+        // The link may have disappeared since (unplugged dock, VPN down); that is not an error.
+        if let Err(e) = run("resolvectl", &["revert", link]) {
+            eprintln!("Skipping link {}: {:#}", link, e);
+        }
+    }
+    let _ = fs::remove_file(record);
+    Ok(())
+}
+
+// --- Plain resolv.conf ---
+
+/// Records the current resolv.conf exactly, unless a snapshot already exists
+/// (a second `--start` must not overwrite the original with our own content).
+fn snapshot_resolv_conf() -> Result<()> {
+    let state = Path::new(STATE_DIR);
+    if state.join(SNAPSHOT_FILE).exists() || state.join(SNAPSHOT_LINK).exists() {
+        return Ok(());
+    }
+    match fs::read_link(RESOLV_CONF) {
+        Ok(target) => fs::write(state.join(SNAPSHOT_LINK), target.as_os_str().as_encoded_bytes())
+            .context("Failed to save resolv.conf symlink target"),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()), // Nothing to preserve
+        Err(_) => {
+            let original = fs::read(RESOLV_CONF).context("Failed to read /etc/resolv.conf")?;
+            fs::write(state.join(SNAPSHOT_FILE), original).context("Failed to save resolv.conf snapshot")
+        }
+    }
+}
+
+/// Replaces resolv.conf via rename, which also replaces a symlink instead of writing through it.
+/// The permissions of a regular file are carried over.
+fn replace_resolv_conf(contents: &[u8]) -> Result<()> {
+    let tmp = format!("{}.cf-toggle.tmp", RESOLV_CONF);
+    fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp))?;
+    if let Ok(meta) = fs::symlink_metadata(RESOLV_CONF)
+        && meta.is_file()
+    {
+        fs::set_permissions(&tmp, meta.permissions())?;
+    }
+    fs::rename(&tmp, RESOLV_CONF).context("Failed to write /etc/resolv.conf")
+}
+
+fn resolv_conf_apply(resolv: &str) -> Result<()> {
+    snapshot_resolv_conf()?;
+    replace_resolv_conf(resolv.as_bytes())
+}
+
+fn resolv_conf_restore(fallback: Option<&str>) -> Result<()> {
+    let state = Path::new(STATE_DIR);
+    let link = state.join(SNAPSHOT_LINK);
+    let file = state.join(SNAPSHOT_FILE);
+
+    if let Ok(target) = fs::read(&link) {
+        let _ = fs::remove_file(RESOLV_CONF);
+        symlink(OsStr::from_bytes(&target), RESOLV_CONF).context("Failed to restore /etc/resolv.conf symlink")?;
+        fs::remove_file(&link)?;
+    } else if let Ok(original) = fs::read(&file) {
+        replace_resolv_conf(&original)?;
+        fs::remove_file(&file)?;
+    } else if let Some(fallback) = fallback {
+        eprintln!("No resolv.conf snapshot found, writing fallback_nameservers instead.");
+        replace_resolv_conf(fallback.as_bytes())?;
+    } else {
+        // Leaving resolv.conf alone would keep it on 127.0.0.1 with the proxy stopped.
+        let resolvers = portal::dhcp_resolvers()
+            .context("No resolv.conf snapshot to restore and no DHCP resolvers to fall back to")?;
+        anyhow::ensure!(
+            !resolvers.is_empty(),
+            "No resolv.conf snapshot to restore and the network handed out no DNS servers; set [toggle] fallback_nameservers",
+        );
+        eprintln!("No resolv.conf snapshot found, writing the DHCP resolvers instead.");
+        replace_resolv_conf(profile::resolv_content(&resolvers).as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Minimal HTTP/1.0 Client
+//!
+//! Just enough to GET a plain-HTTP page over a raw `TcpStream`: the connectivity check and
+//! the local cloudflared metrics endpoint. Not worth a full HTTP crate for two GETs, and the
+//! connectivity check has to pick the address itself (it must not use the system resolver).
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use anyhow::{Context, Result};
+
+const MAX_RESPONSE: u64 = 1024 * 1024;
+
+pub struct Response {
+    pub status: u16,
+    pub location: Option<String>,
+    pub body: String,
+}
+
+/// Splits "http://host[:port]/path" into its parts. Only plain HTTP is supported.
+pub fn parse_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = url.strip_prefix("http://")
+        .with_context(|| format!("URL must be plain http://, got '{}'", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid port in '{}'", url))?),
+        None => (authority, 80),
+    };
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Fetches `path` from `addr`, sending `host` as the Host header.
+/// HTTP/1.0 with `Connection: close` means no chunked encoding: the body is everything after the head.
+pub fn get(addr: SocketAddr, host: &str, path: &str, timeout: Duration) -> Result<Response> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .with_context(|| format!("Cannot connect to {}", addr))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: cf-toggle\r\nConnection: close\r\n\r\n", path, host)?;
+
+    let mut raw = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut raw)?;
+    let response = String::from_utf8_lossy(&raw);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+
+    let status = head.lines().next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .context("Malformed HTTP response")?;
+    let location = head.lines()
+        .find_map(|line| line.split_once(':').filter(|(k, _)| k.eq_ignore_ascii_case("location")))
+        .map(|(_, v)| v.trim().to_string());
+    Ok(Response { status, location, body: body.to_string() })
+}
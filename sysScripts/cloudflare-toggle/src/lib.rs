@@ -0,0 +1,8 @@
+//! Shared code for the `cf-toggle` and `cf-status` binaries.
+
+pub mod dns;
+pub mod http;
+pub mod metrics;
+pub mod portal;
+pub mod probe;
+pub mod profile;
@@ -0,0 +1,135 @@
+//! cloudflared Metrics
+//!
+//! cloudflared's DNS proxy serves Prometheus metrics (the `metrics:` key in its config.yml).
+//! Its DNS handling is CoreDNS underneath, so the series are CoreDNS ones; older and newer
+//! CoreDNS name them differently, so both spellings are accepted.
+//!
+//! The upstreams aren't exported as metrics, so they come from cloudflared's own config.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use crate::http;
+
+/// Where the installer (and docs/MANUAL_INSTALL.md) put cloudflared's config.
+pub const CLOUDFLARED_CONFIG: &str = "/etc/cloudflared/config.yml";
+
+const REQUESTS: [&str; 2] = ["coredns_dns_request_count_total", "coredns_dns_requests_total"];
+const RESPONSES: [&str; 2] = ["coredns_dns_response_rcode_count_total", "coredns_dns_responses_total"];
+// Time from query in to answer out, upstream DoH round trip included. cloudflared doesn't
+// export the upstream leg on its own, so this is the closest figure (and labelled as such).
+const DURATION_BUCKETS: &str = "coredns_dns_request_duration_seconds_bucket";
+
+/// What the tooltip shows. Each figure is None if the endpoint didn't export it.
+pub struct Stats {
+    pub upstreams: Vec<String>,
+    pub requests: Option<u64>,
+    pub errors: Option<u64>, // Responses other than NOERROR/NXDOMAIN (SERVFAIL, REFUSED...)
+    pub p50: Option<Duration>, // Whole proxy request time, not just the upstream leg
+    pub p99: Option<Duration>,
+}
+
+/// One sample line: `name{label="value",...} 123`.
+struct Sample<'a> {
+    name: &'a str,
+    labels: BTreeMap<&'a str, &'a str>,
+    value: f64,
+}
+
+fn parse_sample(line: &str) -> Option<Sample<'_>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (series, rest) = match line.find('}') {
+        Some(end) => (&line[..=end], &line[end + 1..]),
+        None => line.split_once(char::is_whitespace)?,
+    };
+    let (name, labels) = match series.split_once('{') {
+        Some((name, labels)) => (name, labels.trim_end_matches('}')),
+        None => (series, ""),
+    };
+    // Label values here never contain commas or quotes, so a plain split is enough.
+    let labels = labels.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim().trim_matches('"')))
+        .collect();
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Sample { name, labels, value })
+}
+
+/// Estimates a quantile from cumulative histogram buckets, the way Prometheus'
+/// `histogram_quantile` does: linear interpolation inside the bucket the rank falls in.
+fn quantile(buckets: &[(f64, f64)], q: f64) -> Option<Duration> {
+    let total = buckets.last()?.1;
+    if total <= 0.0 {
+        return None;
+    }
+    let rank = q * total;
+    let mut lower = (0.0, 0.0);
+    for &(bound, count) in buckets {
+        if count >= rank {
+            if bound.is_infinite() {
+                return Some(Duration::from_secs_f64(lower.0)); // Best we can say: above the last bound
+            }
+            let share = if count > lower.1 { (rank - lower.1) / (count - lower.1) } else { 0.0 };
+            return Some(Duration::from_secs_f64(lower.0 + (bound - lower.0) * share));
+        }
+        lower = (bound, count);
+    }
+    None
+}
+
+/// The `proxy-dns-upstream` list from cloudflared's config.yml.
+fn configured_upstreams() -> Vec<String> {
+    let Ok(content) = fs::read_to_string(CLOUDFLARED_CONFIG) else { return Vec::new() };
+    content.lines()
+        .skip_while(|line| !line.starts_with("proxy-dns-upstream:"))
+        .skip(1)
+        .take_while(|line| line.trim_start().starts_with('-'))
+        .map(|line| line.trim_start().trim_start_matches('-').trim().trim_matches('"').to_string())
+        .collect()
+}
+
+/// Scrapes `endpoint` ("host:port", or a full "http://host:port/path" URL).
+pub fn scrape(endpoint: &str, timeout: Duration) -> Result<Stats> {
+    let url = if endpoint.starts_with("http://") { endpoint.to_string() } else { format!("http://{}/metrics", endpoint) };
+    let (host, port, path) = http::parse_url(&url)?;
+    let addr = (host.as_str(), port).to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .with_context(|| format!("Cannot resolve metrics host '{}'", host))?;
+    let response = http::get(addr, &host, &path, timeout)?;
+    anyhow::ensure!(response.status == 200, "Metrics endpoint returned HTTP {}", response.status);
+
+    let mut requests = None;
+    let mut errors = None;
+    let mut buckets: BTreeMap<String, (f64, f64)> = BTreeMap::new(); // le -> (bound, summed count)
+    for sample in response.body.lines().filter_map(parse_sample) {
+        if REQUESTS.contains(&sample.name) {
+            *requests.get_or_insert(0.0) += sample.value;
+        } else if RESPONSES.contains(&sample.name) {
+            let rcode = sample.labels.get("rcode").copied().unwrap_or("");
+            let failed = !matches!(rcode, "NOERROR" | "NXDOMAIN");
+            *errors.get_or_insert(0.0) += if failed { sample.value } else { 0.0 };
+        } else if sample.name == DURATION_BUCKETS
+            && let Some(le) = sample.labels.get("le")
+            && let Ok(bound) = le.parse::<f64>()
+        {
+            // Summed over server/zone/type: the overall request time is what the bar cares about.
+            buckets.entry(le.to_string()).or_insert((bound, 0.0)).1 += sample.value;
+        }
+    }
+    let mut buckets: Vec<(f64, f64)> = buckets.into_values().collect();
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Ok(Stats {
+        upstreams: configured_upstreams(),
+        requests: requests.map(|r: f64| r as u64),
+        errors: errors.map(|e: f64| e as u64),
+        p50: quantile(&buckets, 0.5),
+        p99: quantile(&buckets, 0.99),
+    })
+}
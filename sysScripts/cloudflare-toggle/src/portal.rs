@@ -0,0 +1,81 @@
+//! Captive Portal Detection
+//!
+//! Behind a hotel/airport portal, encrypted DNS can't reach its upstream, so the portal
+//! page never resolves. To see past that, the check bypasses the system resolver: the
+//! connectivity-check host is resolved through the *network's own* (DHCP) resolvers and
+//! fetched over plain HTTP. A clean network returns the expected body; a portal answers
+//! with a redirect or its own login page instead.
+
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use crate::{http, probe};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Connectivity {
+    Clean,
+    Portal(String), // What gave it away (redirect target, unexpected status...)
+    Offline(String),
+}
+
+/// DNS servers handed out by DHCP/RA, as NetworkManager reports them.
+/// These are still valid even when NM is told not to manage resolv.conf (`dns=none`).
+pub fn dhcp_resolvers() -> Result<Vec<IpAddr>> {
+    let output = Command::new("nmcli")
+        .args(["-t", "-f", "IP4.DNS,IP6.DNS", "device", "show"])
+        .output()
+        .context("Failed to run nmcli")?;
+    anyhow::ensure!(output.status.success(), "nmcli device show failed");
+
+    let mut resolvers = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // "IP4.DNS[1]:192.168.1.1"; IPv6 values contain colons, so split on the first one only.
+        let Some((_, value)) = line.split_once(':') else { continue };
+        if let Ok(ip) = value.trim().parse::<IpAddr>()
+            && !ip.is_loopback()
+            && !resolvers.contains(&ip)
+        {
+            resolvers.push(ip);
+        }
+    }
+    Ok(resolvers)
+}
+
+/// Identifies the current network: the UUIDs of NetworkManager's active connections.
+/// Changes on every new Wi-Fi/Ethernet connection; stable across DHCP renewals.
+pub fn network_id() -> Result<String> {
+    let output = Command::new("nmcli")
+        .args(["-t", "-f", "UUID", "connection", "show", "--active"])
+        .output()
+        .context("Failed to run nmcli")?;
+    anyhow::ensure!(output.status.success(), "nmcli connection show failed");
+    let mut uuids: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect();
+    uuids.sort();
+    Ok(uuids.join(","))
+}
+
+/// Probes `url` through `resolvers` and classifies the network.
+pub fn check(resolvers: &[IpAddr], url: &str, expect: &str) -> Result<Connectivity> {
+    // Only plain HTTP makes sense here: a portal can't answer for an HTTPS host without a certificate error.
+    let (host, port, path) = http::parse_url(url)?;
+    if resolvers.is_empty() {
+        return Ok(Connectivity::Offline("No DHCP resolvers known".to_string()));
+    }
+
+    // Any resolver that answers will do; portals typically answer everything with their own IP.
+    let resolved = resolvers.iter().find_map(|r| probe::resolve_a(SocketAddr::new(*r, 53), &host, TIMEOUT).ok());
+    let Some(ip) = resolved else {
+        return Ok(Connectivity::Offline(format!("Could not resolve {}", host)));
+    };
+
+    match http::get(SocketAddr::new(IpAddr::V4(ip), port), &host, &path, TIMEOUT) {
+        Err(e) => Ok(Connectivity::Offline(format!("{:#}", e))),
+        Ok(r) if r.status == 200 && r.body.contains(expect) => Ok(Connectivity::Clean),
+        Ok(http::Response { status, location: Some(location), .. }) if (300..400).contains(&status) => {
+            Ok(Connectivity::Portal(format!("Redirected to {}", location)))
+        }
+        Ok(r) => Ok(Connectivity::Portal(format!("Unexpected HTTP {} from {}", r.status, host))),
+    }
+}
@@ -0,0 +1,198 @@
+//! DNS Health Probe
+//!
+//! Sends a single hand-built `A` query over UDP and times the answer. This is what the
+//! status module trusts instead of `systemctl is-active`: a running unit whose upstream
+//! is wedged still answers SERVFAIL (or nothing at all), and that must show as degraded.
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_REFUSED: u8 = 5;
+
+/// Builds a minimal recursive query for `hostname` (type A, class IN).
+fn build_query(id: u16, hostname: &str) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(32 + hostname.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&[0x01, 0x00]); // Flags: standard query, recursion desired
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // QDCOUNT=1, AN/NS/AR=0
+    for label in hostname.trim_end_matches('.').split('.') {
+        anyhow::ensure!(!label.is_empty() && label.len() < 64, "Invalid test hostname '{}'", hostname);
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&[0, 1, 0, 1]); // QTYPE=A, QCLASS=IN
+    Ok(packet)
+}
+
+/// Sends the query and waits for the matching response. Returns the raw packet and the RTT.
+fn exchange(server: SocketAddr, hostname: &str, timeout: Duration) -> Result<(Vec<u8>, Duration)> {
+    // Not security relevant, just enough to tell our reply from a stray packet.
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u16).unwrap_or(0)
+        ^ std::process::id() as u16;
+    let packet = build_query(id, hostname)?;
+
+    let bind_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind_addr).context("Failed to open UDP socket")?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server).with_context(|| format!("Failed to reach {}", server))?;
+
+    let started = Instant::now();
+    socket.send(&packet).context("Failed to send DNS query")?;
+    let mut buf = [0u8; 512];
+    loop {
+        let len = socket.recv(&mut buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                anyhow::anyhow!("No answer within {} ms", timeout.as_millis())
+            }
+            _ => anyhow::anyhow!("DNS query failed: {}", e),
+        })?;
+        // Skip anything that isn't a response to this query.
+        if len < 3 || buf[..2] != id.to_be_bytes() || buf[2] & 0x80 == 0 {
+            if started.elapsed() >= timeout {
+                anyhow::bail!("No answer within {} ms", timeout.as_millis());
+            }
+            continue;
+        }
+        // Our reply, but cut short of even a full header: the resolver is broken, not slow.
+        anyhow::ensure!(len >= 12, "Truncated DNS response ({} bytes)", len);
+        return Ok((buf[..len].to_vec(), started.elapsed()));
+    }
+}
+
+/// Turns a non-success response code into an error.
+fn check_rcode(response: &[u8], allow_nxdomain: bool) -> Result<()> {
+    let flags = response.get(3).context("Truncated DNS response")?;
+    match flags & 0x0f {
+        RCODE_NOERROR => Ok(()),
+        RCODE_NXDOMAIN if allow_nxdomain => Ok(()),
+        RCODE_NXDOMAIN => anyhow::bail!("Name does not exist"),
+        RCODE_SERVFAIL => anyhow::bail!("Resolver returned SERVFAIL"),
+        RCODE_REFUSED => anyhow::bail!("Resolver refused the query"),
+        rcode => anyhow::bail!("Resolver returned error code {}", rcode),
+    }
+}
+
+/// Resolves `hostname` through `server` and returns the round-trip time.
+/// Fails on timeout, a mismatched reply, or any response code other than NOERROR/NXDOMAIN
+/// (NXDOMAIN still proves the resolver works; SERVFAIL is what a wedged upstream returns).
+pub fn query(server: SocketAddr, hostname: &str, timeout: Duration) -> Result<Duration> {
+    let (response, rtt) = exchange(server, hostname, timeout)?;
+    check_rcode(&response, true)?;
+    Ok(rtt)
+}
+
+/// Skips an encoded (possibly compressed) name starting at `pos`, returning the offset after it.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2), // Compression pointer ends the name
+            l => pos += 1 + l,
+        }
+    }
+}
+
+/// Resolves `hostname` to its first IPv4 address through `server` specifically,
+/// bypassing the system resolver (used to reach the network's own DNS behind our back).
+pub fn resolve_a(server: SocketAddr, hostname: &str, timeout: Duration) -> Result<Ipv4Addr> {
+    let (response, _) = exchange(server, hostname, timeout)?;
+    check_rcode(&response, false)?;
+
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+    // Header (12 bytes), then the echoed question: name + QTYPE + QCLASS.
+    let mut pos = skip_name(&response, 12).context("Truncated DNS response")? + 4;
+    for _ in 0..answers {
+        pos = skip_name(&response, pos).context("Truncated DNS response")?;
+        let header = response.get(pos..pos + 10).context("Truncated DNS response")?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rdlength = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10;
+        let rdata = response.get(pos..pos + rdlength).context("Truncated DNS response")?;
+        // CNAMEs come first in the answer section; take the first actual A record.
+        if rtype == 1 && rdlength == 4 {
+            return Ok(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]));
+        }
+        pos += rdlength;
+    }
+    anyhow::bail!("No A record for {}", hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// A one-shot resolver on localhost: answers the first query with whatever `reply`
+    /// makes of it, and returns the address to query.
+    fn stub_resolver(reply: fn(&[u8]) -> Vec<u8>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            socket.send_to(&reply(&buf[..len]), from).unwrap();
+        });
+        addr
+    }
+
+    /// The query echoed back as a response with `rcode` and one A record (93.184.216.34).
+    fn response(query: &[u8], rcode: u8) -> Vec<u8> {
+        let mut packet = query.to_vec();
+        packet[2] |= 0x80; // QR: response
+        packet[3] = 0x80 | rcode; // RA + rcode
+        packet[6..8].copy_from_slice(&1u16.to_be_bytes()); // ANCOUNT=1
+        packet.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        packet
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn noerror_is_healthy() {
+        let server = stub_resolver(|q| response(q, RCODE_NOERROR));
+        assert!(query(server, "example.com", TIMEOUT).is_ok());
+    }
+
+    #[test]
+    fn nxdomain_still_proves_the_resolver_works() {
+        let server = stub_resolver(|q| response(q, RCODE_NXDOMAIN));
+        assert!(query(server, "example.com", TIMEOUT).is_ok());
+    }
+
+    #[test]
+    fn servfail_is_an_error() {
+        let server = stub_resolver(|q| response(q, RCODE_SERVFAIL));
+        let err = query(server, "example.com", TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("SERVFAIL"), "{:#}", err);
+    }
+
+    #[test]
+    fn truncated_reply_is_an_error() {
+        let server = stub_resolver(|q| response(q, RCODE_NOERROR)[..8].to_vec());
+        let err = query(server, "example.com", TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("Truncated"), "{:#}", err);
+    }
+
+    #[test]
+    fn resolve_a_reads_the_answer() {
+        let server = stub_resolver(|q| response(q, RCODE_NOERROR));
+        assert_eq!(resolve_a(server, "example.com", TIMEOUT).unwrap(), Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[test]
+    fn check_rcode_classifies_codes() {
+        let header = |rcode: u8| vec![0, 0, 0x81, 0x80 | rcode];
+        assert!(check_rcode(&header(RCODE_NOERROR), false).is_ok());
+        assert!(check_rcode(&header(RCODE_NXDOMAIN), true).is_ok());
+        assert!(check_rcode(&header(RCODE_NXDOMAIN), false).is_err());
+        assert!(check_rcode(&header(RCODE_SERVFAIL), true).is_err());
+        assert!(check_rcode(&header(RCODE_REFUSED), true).is_err());
+        assert!(check_rcode(&[0, 0], true).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Named DNS Profiles
+//!
+//! What each profile actually does lives in a root-owned file, `PROFILES_FILE`, so the
+//! privileged half never takes resolver content from the (unprivileged) caller: it is only
+//! told a profile *name*. Each `[profiles.<name>]` entry lists the nameservers to use and an
+//! optional systemd unit that has to run for them (cloudflared, unbound...). A profile with
+//! no nameservers means "whatever the network handed out" and restores the original setup.
+//! The `[toggle]` section describes the plain on/off click.
+//!
+//! How a profile *looks* in the bar (text/class) stays in the user's config.toml.
+//!
+//! The root half records the active profile (and the unit it started) under `STATE_DIR`,
+//! which is also how `cf-status` knows what to display.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::dns::STATE_DIR;
+
+pub const PROFILES_FILE: &str = "/etc/cf-toggle/profiles.toml";
+/// Recorded as the active profile while the captive-portal fallback is in effect.
+pub const PORTAL_PROFILE: &str = "captive-portal";
+
+const ACTIVE_PROFILE: &str = "active-profile";
+const ACTIVE_UNIT: &str = "active-unit";
+const PORTAL_RETURN: &str = "portal-return"; // Profile to go back to after the portal (absent = toggle)
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Profile {
+    #[serde(default)]
+    pub nameservers: Vec<IpAddr>, // Empty = ISP/DHCP default (restore the original resolver)
+    pub unit: Option<String>,     // systemd unit started with this profile, stopped when leaving it
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Toggle {
+    #[serde(default = "default_toggle_unit")]
+    pub unit: String,                      // Unit started by the plain toggle
+    pub nameservers: Vec<IpAddr>,          // Where DNS points while it is on
+    #[serde(default)]
+    pub fallback_nameservers: Vec<IpAddr>, // Written on "off" only if there is no snapshot to restore
+}
+
+fn default_toggle_unit() -> String { "cloudflared-dns".to_string() }
+
+#[derive(Deserialize, Debug)]
+pub struct SystemProfiles {
+    pub toggle: Toggle,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// systemd unit names only use this alphabet; anything else (or a leading `-`, which
+/// systemctl would take as an option) is rejected before it reaches a command line.
+fn is_valid_unit(unit: &str) -> bool {
+    !unit.is_empty()
+        && !unit.starts_with('-')
+        && unit.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '.' | '@' | '-' | '\\'))
+}
+
+/// Refuses files (or directories) that someone other than root could have written.
+fn ensure_root_owned(path: &Path) -> Result<()> {
+    let meta = fs::metadata(path).with_context(|| format!("Cannot stat {}", path.display()))?;
+    anyhow::ensure!(meta.uid() == 0, "{} must be owned by root", path.display());
+    anyhow::ensure!(meta.mode() & 0o022 == 0, "{} must not be group- or world-writable", path.display());
+    Ok(())
+}
+
+/// Loads and validates the system profile file.
+pub fn load_system() -> Result<SystemProfiles> {
+    let path = Path::new(PROFILES_FILE);
+    if let Some(dir) = path.parent() {
+        ensure_root_owned(dir)?;
+    }
+    ensure_root_owned(path)?;
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", PROFILES_FILE))?;
+    let system: SystemProfiles = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", PROFILES_FILE))?;
+
+    let units = std::iter::once(&system.toggle.unit)
+        .chain(system.profiles.values().filter_map(|p| p.unit.as_ref()));
+    for unit in units {
+        anyhow::ensure!(is_valid_unit(unit), "Invalid unit name '{}' in {}", unit, PROFILES_FILE);
+    }
+    Ok(system)
+}
+
+/// The resolv.conf body for a list of nameservers.
+pub fn resolv_content(nameservers: &[IpAddr]) -> String {
+    nameservers.iter().map(|ns| format!("nameserver {}\n", ns)).collect()
+}
+
+fn state_file(name: &str) -> PathBuf {
+    Path::new(STATE_DIR).join(name)
+}
+
+fn read_state(name: &str) -> Option<String> {
+    fs::read_to_string(state_file(name))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn write_state(name: &str, value: Option<&str>) -> std::io::Result<()> {
+    match value {
+        Some(value) => fs::write(state_file(name), value),
+        None => match fs::remove_file(state_file(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// The unit `--stop` disables: the one recorded when the current state was entered, or the
+/// toggle's own unit when nothing is recorded (the installer enables cloudflared directly, and
+/// older versions didn't record it), so the toggle can always turn it off.
+pub fn unit_to_stop(recorded: Option<String>, toggle: &Toggle) -> String {
+    recorded.unwrap_or_else(|| toggle.unit.clone())
+}
+
+/// Name of the profile selected last, if any (None after a plain on/off toggle).
+pub fn active_profile() -> Option<String> {
+    read_state(ACTIVE_PROFILE)
+}
+
+/// The systemd unit the current state depends on, so switching away can stop it.
+/// It ends up on a root command line too, so it gets the same check as the profile file.
+pub fn active_unit() -> Option<String> {
+    read_state(ACTIVE_UNIT).filter(|unit| is_valid_unit(unit))
+}
+
+/// The profile that was active before the captive-portal fallback (None = plain toggle).
+pub fn portal_return() -> Option<String> {
+    read_state(PORTAL_RETURN)
+}
+
+/// Records the new state (root only). `portal_return` is only kept while in the portal fallback.
+pub fn record(profile: Option<&str>, unit: Option<&str>, portal_return: Option<&str>) -> std::io::Result<()> {
+    fs::create_dir_all(STATE_DIR)?;
+    write_state(ACTIVE_PROFILE, profile)?;
+    write_state(ACTIVE_UNIT, unit)?;
+    write_state(PORTAL_RETURN, portal_return.filter(|_| profile == Some(PORTAL_PROFILE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(toml: &str) -> SystemProfiles {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn stop_falls_back_to_the_toggle_unit_when_nothing_is_recorded() {
+        // Fresh install: the installer enabled cloudflared-dns, cf-toggle never recorded it
+        let system = system("[toggle]\nnameservers = [\"127.0.0.1\"]\n");
+        assert_eq!(unit_to_stop(None, &system.toggle), "cloudflared-dns");
+    }
+
+    #[test]
+    fn stop_uses_the_recorded_unit() {
+        let system = system("[toggle]\nunit = \"cloudflared-dns\"\nnameservers = [\"127.0.0.1\"]\n");
+        assert_eq!(unit_to_stop(Some("unbound".to_string()), &system.toggle), "unbound");
+    }
+
+    #[test]
+    fn unit_names_are_checked() {
+        assert!(is_valid_unit("cloudflared-dns.service"));
+        assert!(is_valid_unit("getty@tty1.service"));
+        assert!(!is_valid_unit(""));
+        assert!(!is_valid_unit("--now"));
+        assert!(!is_valid_unit("foo; rm -rf /"));
+    }
+
+    #[test]
+    fn nameservers_must_be_ip_addresses() {
+        assert!(toml::from_str::<SystemProfiles>("[toggle]\nnameservers = [\"dns.example\"]\n").is_err());
+        assert_eq!(resolv_content(&system("[toggle]\nnameservers = [\"1.1.1.1\", \"::1\"]\n").toggle.nameservers),
+            "nameserver 1.1.1.1\nnameserver ::1\n");
+    }
+}
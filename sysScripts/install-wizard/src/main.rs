@@ -80,7 +80,7 @@ const COMMON_PACKAGES: &[&str] = &[
     // Shell / Apps
     "zsh", "starship", "ghostty", "tmux", "fzf", "ripgrep", "bat", "btop", "fastfetch", "neovim",
     "networkmanager", "network-manager-applet", "cloudflared",
-    "firefox", "discord", "tigervnc", "mpv", "gparted", "simple-scan", "gnome-calculator",
+    "firefox", "discord", "tigervnc", "mpv", "ffmpeg", "gparted", "simple-scan", "gnome-calculator",
     "cups", "system-config-printer", "cups-pdf", "zsh-autosuggestions", "zsh-syntax-highlighting"
 ];
 
@@ -97,7 +97,7 @@ const AMD_PACKAGES: &[&str] = &[
 // AUR
 const AUR_PACKAGES: &[&str] = &[
     "wlogout", "zoom", "slack-desktop", "ledger-live-bin", 
-    "visual-studio-code-bin", "pinta", "ttf-victor-mono", "ytmdesktop-bin"
+    "visual-studio-code-bin", "pinta", "ttf-victor-mono", "ytmdesktop-bin", "mpvpaper"
 ];
 // ---------- Main Execution ------_-------
 fn main() {
@@ -195,6 +195,7 @@ fn main() {
     let _ = Command::new("rustup").args(["default", "stable"]).status();
     // Compiles custom rust scripts, installs to ~/.cargo/bin
     build_custom_apps();
+    install_cf_toggle_helper();
 
     // 7. Link Dotfiles & Copy Wallpapers
     println!("\n{}", "🔗 Linking Config Files & Resources...".blue().bold());
@@ -363,7 +364,7 @@ fn configure_system() {
     println!("   🔧 Configuring Cloudflared (DNS Proxy)...");
     
     // 1. Write the Config File
-    let cf_config = "proxy-dns: true\nproxy-dns-upstream:\n  - https://1.1.1.1/dns-query\n  - https://1.0.0.1/dns-query\nproxy-dns-port: 53\nproxy-dns-address: 127.0.0.1\n";
+    let cf_config = "proxy-dns: true\nproxy-dns-upstream:\n  - https://1.1.1.1/dns-query\n  - https://1.0.0.1/dns-query\nproxy-dns-port: 53\nproxy-dns-address: 127.0.0.1\nmetrics: 127.0.0.1:49312\n";
     let _ = Command::new("sudo").args(["mkdir", "-p", "/etc/cloudflared"]).status();
     
     let local_cf_conf = "./config.yml";
@@ -806,6 +807,29 @@ fn build_custom_apps() {
     }
 }
 
+/// Installs the privileged half of cf-toggle: a root-owned binary, its polkit action and
+/// the root-owned DNS profile file. pkexec must never run the user-writable ~/.cargo/bin copy.
+fn install_cf_toggle_helper() {
+    println!("   🔧 Installing cf-toggle privileged helper...");
+    let current_dir = std::env::current_dir().unwrap();
+    let cf_dir = current_dir.parent().unwrap().join("cloudflare-toggle");
+    let built = dirs::home_dir().unwrap().join(".cargo/bin/cf-toggle");
+    let policy = cf_dir.join("system/io.github.mccalabrese.cf-toggle.policy");
+    let profiles = cf_dir.join("system/profiles.toml");
+
+    if !built.exists() || !policy.exists() {
+        println!("     ⚠️  cf-toggle not built, skipping helper install");
+        return;
+    }
+    run_cmd("sudo", &["install", "-m", "755", "-o", "root", "-g", "root", built.to_str().unwrap(), "/usr/local/bin/cf-toggle"]);
+    run_cmd("sudo", &["install", "-m", "644", policy.to_str().unwrap(), "/usr/share/polkit-1/actions/"]);
+    // Keep an existing profile file: the user may have added their own resolvers.
+    if !Path::new("/etc/cf-toggle/profiles.toml").exists() {
+        run_cmd("sudo", &["install", "-D", "-m", "644", "-o", "root", "-g", "root", profiles.to_str().unwrap(), "/etc/cf-toggle/profiles.toml"]);
+    }
+    println!("     ✅ cf-toggle helper");
+}
+
 /// Renames session files to enforce a specific order in Greetd/Tuigreet.
 /// Strategy: Move standard files (e.g. hyprland.desktop) to custom numbered files (30-hyprland.desktop).
 /// This prevents Pacman from deleting our custom config during updates while NoExtract is active.
@@ -868,6 +892,7 @@ fn link_dotfiles_and_copy_resources() {
         (".config/wlogout", ".config/wlogout"),
         (".config/gtk-3.0", ".config/gtk-3.0"), (".config/gtk-4.0", ".config/gtk-4.0"),
         (".config/environment.d", ".config/environment.d"), (".config/mako", ".config/mako"),
+        (".config/rust-dotfiles/theme-templates", ".config/rust-dotfiles/theme-templates"),
     ];
 
     for (src, dest) in links {
@@ -0,0 +1,168 @@
+//! Automatic workspace naming (Sway only).
+//!
+//! Walks the sway tree and renames every numbered workspace to `<num>: <icons>`,
+//! where each icon is looked up from the window's `app_id` (Wayland) or `class` (XWayland)
+//! in the `[sway_workspace]` section of `config.toml`. Runs as a daemon and re-applies
+//! names whenever windows open, close or move between workspaces.
+
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use swayipc::{Connection, Event, EventType, Node, NodeType, WindowChange};
+use crate::sway;
+use crate::RECONNECT_DELAY;
+
+// --- Config Models ---
+
+#[derive(Deserialize, Debug)]
+struct SwayWorkspaceConfig {
+    #[serde(default)]
+    icons: HashMap<String, String>, // app_id / class (case-insensitive) -> glyph
+    #[serde(default = "default_icon")]
+    default_icon: String,           // Used for windows with no entry in `icons`
+    #[serde(default = "default_true")]
+    dedupe_icons: bool,             // Show a browser icon once even with three browser windows
+}
+
+fn default_icon() -> String { "\u{f2d0}".to_string() } // nf-fa-window_maximize
+fn default_true() -> bool { true }
+
+// Same values as an empty `[sway_workspace]` table (a derive would zero the serde defaults).
+impl Default for SwayWorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            icons: HashMap::new(),
+            default_icon: default_icon(),
+            dedupe_icons: default_true(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GlobalConfig {
+    // Optional: the daemon starts at login, and older configs have no such table.
+    #[serde(default)]
+    sway_workspace: SwayWorkspaceConfig,
+}
+
+fn load_config() -> Result<GlobalConfig> {
+    let config_path = dirs::home_dir()
+        .context("Cannot find home dir")?
+        .join(".config/rust-dotfiles/config.toml");
+    let config_str = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file from path: {}", config_path.display()))?;
+    let config: GlobalConfig = toml::from_str(&config_str)
+        .context("Failed to parse config.toml. Check for syntax errors.")?;
+    Ok(config)
+}
+
+// --- Tree Walking ---
+
+/// Collects the identifier (app_id, or X11 class as a fallback) of every window below `node`.
+fn collect_app_ids(node: &Node, out: &mut Vec<String>) {
+    let is_window = matches!(node.node_type, NodeType::Con | NodeType::FloatingCon)
+        && node.nodes.is_empty()
+        && node.floating_nodes.is_empty();
+    if is_window {
+        let id = node.app_id.clone().or_else(|| {
+            node.window_properties.as_ref().and_then(|p| p.class.clone())
+        });
+        if let Some(id) = id {
+            out.push(id);
+        }
+    }
+    for child in node.nodes.iter().chain(node.floating_nodes.iter()) {
+        collect_app_ids(child, out);
+    }
+}
+
+/// Finds every workspace node in the tree, skipping sway's internal scratchpad.
+fn collect_workspaces<'a>(node: &'a Node, out: &mut Vec<&'a Node>) {
+    if node.node_type == NodeType::Workspace {
+        if node.name.as_deref() != Some("__i3_scratch") {
+            out.push(node);
+        }
+        return;
+    }
+    for child in &node.nodes {
+        collect_workspaces(child, out);
+    }
+}
+
+/// Builds the desired label for a workspace from the windows it holds.
+fn desired_name(num: i32, app_ids: &[String], config: &SwayWorkspaceConfig) -> String {
+    let mut icons: Vec<&str> = Vec::new();
+    for app_id in app_ids {
+        let icon = config
+            .icons
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(app_id))
+            .map(|(_, glyph)| glyph.as_str())
+            .unwrap_or(config.default_icon.as_str());
+        if !(config.dedupe_icons && icons.contains(&icon)) {
+            icons.push(icon);
+        }
+    }
+    if icons.is_empty() {
+        num.to_string()
+    } else {
+        format!("{}: {}", num, icons.join(" "))
+    }
+}
+
+/// Renames every numbered workspace whose label no longer matches its windows.
+fn apply_names(client: &mut sway::Client, config: &SwayWorkspaceConfig) -> Result<()> {
+    let tree = client.tree()?;
+    let mut workspaces = Vec::new();
+    collect_workspaces(&tree, &mut workspaces);
+
+    for ws in workspaces {
+        // Purely named workspaces (no leading number) are left untouched.
+        let (Some(num), Some(current)) = (ws.num.filter(|n| *n >= 0), ws.name.as_deref()) else {
+            continue;
+        };
+        let mut app_ids = Vec::new();
+        collect_app_ids(ws, &mut app_ids);
+        let wanted = desired_name(num, &app_ids, config);
+        if wanted != current {
+            client.rename(Some(current), &wanted)?;
+        }
+    }
+    Ok(())
+}
+
+/// One connected session: name everything once, then re-apply on window events.
+fn stream_events(config: &SwayWorkspaceConfig) -> Result<()> {
+    let mut client = sway::Client::connect()?;
+    apply_names(&mut client, config)?;
+
+    let events = Connection::new()
+        .context("Failed to open sway IPC event connection")?
+        .subscribe([EventType::Window])
+        .context("Failed to subscribe to sway window events")?;
+
+    for event in events {
+        let event = event.context("Sway IPC event stream failed")?;
+        // Only changes to which windows live where affect the icons.
+        if let Event::Window(window) = event
+            && matches!(window.change, WindowChange::New | WindowChange::Close | WindowChange::Move)
+        {
+            apply_names(&mut client, config)?;
+        }
+    }
+    Ok(())
+}
+
+/// Daemon entry point. Never returns; reconnects if sway restarts.
+pub fn run() -> Result<()> {
+    let config = load_config()?.sway_workspace;
+    loop {
+        match stream_events(&config) {
+            Ok(()) => eprintln!("sway-workspace autoname: event stream closed, reconnecting..."),
+            Err(e) => eprintln!("sway-workspace autoname: {:#}, reconnecting...", e),
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
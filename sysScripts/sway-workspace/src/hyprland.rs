@@ -0,0 +1,182 @@
+//! Hyprland backend.
+//!
+//! Hyprland exposes two Unix sockets per instance under
+//! `$XDG_RUNTIME_DIR/hypr/$HYPRLAND_INSTANCE_SIGNATURE/`:
+//! - `.socket.sock`  accepts one request per connection (`j/<command>` returns JSON).
+//! - `.socket2.sock` streams `EVENT>>DATA` lines for every state change.
+//!
+//! Urgency isn't part of any query reply, only of the `urgent>>ADDRESS` event, so the event
+//! loop keeps the set of urgent windows (cleared when the window is focused or closed) and
+//! `workspaces()` maps them to their workspaces through `j/clients`.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::WorkspaceInfo;
+
+// --- IPC Structures ---
+// These match the JSON output of `hyprctl -j workspaces` / `hyprctl -j monitors`.
+#[derive(Deserialize, Debug)]
+struct HyprWorkspace {
+    id: i32,
+    name: String,
+    monitor: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct HyprMonitor {
+    focused: bool,
+    active_workspace: HyprWorkspaceRef,
+}
+
+#[derive(Deserialize, Debug)]
+struct HyprWorkspaceRef {
+    id: i32,
+}
+
+#[derive(Deserialize, Debug)]
+struct HyprClient {
+    address: String, // "0x55d9c7e5a6f0"; events carry it without the "0x"
+    workspace: HyprWorkspaceRef,
+}
+
+/// Addresses (without "0x") of windows that asked for attention and weren't focused since.
+/// Written by the event loop, read by `workspaces()`; both run on the same thread.
+static URGENT_WINDOWS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn urgent_windows() -> std::sync::MutexGuard<'static, BTreeSet<String>> {
+    URGENT_WINDOWS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Events that can change which workspace is focused or how it is labelled.
+const RELEVANT_EVENTS: &[&str] = &[
+    "workspace",
+    "workspacev2",
+    "focusedmon",
+    "focusedmonv2",
+    "createworkspace",
+    "createworkspacev2",
+    "destroyworkspace",
+    "destroyworkspacev2",
+    "renameworkspace",
+    "moveworkspace",
+    "moveworkspacev2",
+    "monitoradded",
+    "monitoraddedv2",
+    "monitorremoved",
+];
+
+/// Locates the socket directory for the running instance.
+/// Hyprland >= 0.40 uses `$XDG_RUNTIME_DIR/hypr`, older releases used `/tmp/hypr`.
+fn socket_dir() -> Result<PathBuf> {
+    let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE")
+        .context("HYPRLAND_INSTANCE_SIGNATURE is not set. Is Hyprland running?")?;
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        let dir = PathBuf::from(runtime_dir).join("hypr").join(&signature);
+        if dir.exists() {
+            return Ok(dir);
+        }
+    }
+    Ok(PathBuf::from("/tmp/hypr").join(signature))
+}
+
+pub struct Client {
+    socket_dir: PathBuf,
+}
+
+impl Client {
+    pub fn connect() -> Result<Self> {
+        Ok(Self { socket_dir: socket_dir()? })
+    }
+
+    /// Sends a single `j/` request and parses the JSON reply.
+    fn request<T: for<'de> Deserialize<'de>>(&self, command: &str) -> Result<T> {
+        let path = self.socket_dir.join(".socket.sock");
+        let mut stream = UnixStream::connect(&path)
+            .with_context(|| format!("Failed to connect to Hyprland socket {:?}", path))?;
+        stream.write_all(format!("j/{}", command).as_bytes())?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        serde_json::from_str(&reply)
+            .with_context(|| format!("Failed to parse Hyprland '{}' reply", command))
+    }
+
+    pub fn workspaces(&mut self) -> Result<Vec<WorkspaceInfo>> {
+        let workspaces: Vec<HyprWorkspace> = self.request("workspaces")?;
+        let monitors: Vec<HyprMonitor> = self.request("monitors")?;
+        let focused_id = monitors
+            .iter()
+            .find(|m| m.focused)
+            .map(|m| m.active_workspace.id);
+        // Every monitor shows exactly one workspace; those are the "visible" ones.
+        let visible_ids: Vec<i32> = monitors.iter().map(|m| m.active_workspace.id).collect();
+        let urgent_ids = self.urgent_workspaces()?;
+
+        Ok(workspaces
+            .into_iter()
+            .filter(|ws| ws.id > 0) // Negative IDs are special (scratchpad) workspaces
+            .map(|ws| WorkspaceInfo {
+                focused: Some(ws.id) == focused_id,
+                visible: visible_ids.contains(&ws.id),
+                urgent: urgent_ids.contains(&ws.id),
+                num: ws.id,
+                name: ws.name,
+                output: ws.monitor,
+            })
+            .collect())
+    }
+}
+
+impl Client {
+    /// Workspaces holding an urgent window. Windows that are gone are forgotten.
+    fn urgent_workspaces(&self) -> Result<Vec<i32>> {
+        if urgent_windows().is_empty() {
+            return Ok(Vec::new()); // The common case: no extra request
+        }
+        let clients: Vec<HyprClient> = self.request("clients")?;
+        let mut urgent = urgent_windows();
+        let mut ids = Vec::new();
+        urgent.retain(|address| {
+            let window = clients.iter().find(|c| c.address.trim_start_matches("0x") == address);
+            if let Some(window) = window {
+                ids.push(window.workspace.id);
+            }
+            window.is_some()
+        });
+        Ok(ids)
+    }
+}
+
+/// Updates the urgent set from one event. Returns true if it changed.
+fn track_urgency(event: &str, data: &str) -> bool {
+    let address = data.split(',').next().unwrap_or_default().trim_start_matches("0x");
+    match event {
+        "urgent" => urgent_windows().insert(address.to_string()),
+        // Focusing (or closing) the window is what clears its urgency.
+        "activewindowv2" | "closewindow" => urgent_windows().remove(address),
+        _ => false,
+    }
+}
+
+/// Follows `.socket2.sock` and calls `on_event` for every workspace/monitor change.
+/// Returns Ok when Hyprland closes the socket.
+pub fn wait_for_events(mut on_event: impl FnMut() -> Result<()>) -> Result<()> {
+    let path = socket_dir()?.join(".socket2.sock");
+    let stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to Hyprland event socket {:?}", path))?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line.context("Hyprland event stream failed")?;
+        let (event, data) = line.split_once(">>").unwrap_or((&line, ""));
+        if track_urgency(event, data) || RELEVANT_EVENTS.contains(&event) {
+            on_event()?;
+        }
+    }
+    Ok(())
+}
@@ -1,34 +1,385 @@
-//! Sway Workspace Fetcher
+//! Workspace Fetcher (sway-workspace)
 //!
-//! A minimal IPC client that queries the Sway Window Manager for the currently focused workspace.
+//! A minimal IPC client that queries the running Wayland compositor for its workspaces.
 //! Designed for use in status bars (like Waybar) or shell scripts that need context awareness
 //! of the window manager's state.
+//!
+//! Despite the name, the backend is chosen from the environment (Sway, Hyprland or Niri),
+//! so a single Waybar module definition works in every session.
+//!
+//! Usage:
+//!   sway-workspace                 => Prints the focused workspace name once and exits.
+//!   sway-workspace --watch         => Stays alive and prints a fresh line on every workspace/output event.
+//!   sway-workspace --json          => Emits Waybar JSON objects instead of plain text (combines with --watch).
+//!   sway-workspace --bar           => Emits Waybar JSON describing *every* workspace (implies --json).
+//!                                     On Hyprland urgency comes from events, so it needs --watch too.
+//!   sway-workspace --output <NAME> => Restricts the output above to workspaces on a single monitor.
+//!
+//! Actions (Sway only), for keybindings and Waybar `on-click` handlers:
+//!   sway-workspace switch <WS>                  => Focus a workspace (number or name).
+//!   sway-workspace move-container <WS> [--follow] => Move the focused window, optionally following it.
+//!   sway-workspace rename [<OLD>] <NEW>         => Rename a workspace (the focused one by default).
+//!   sway-workspace next-free [--move]           => Jump to (or move the window to) the first unused number.
+//!   sway-workspace autoname                     => Daemon: rename workspaces to "<num>: <icons>" from their windows.
+
+mod autoname;
+mod hyprland;
+mod niri;
+mod sway;
 
+use std::env;
+use std::thread;
+use std::time::Duration;
 use anyhow::{Context, Result};
-use swayipc::Connection;
+use serde::Serialize;
+use serde_json::json;
 
-fn main() -> Result<()> {
-    // 1. Establish IPC Connection
-    // Connects to the Unix socket defined in the $SWAYSOCK environment variable.
-    // Use the `swayipc` crate to abstract the low-level JSON-IPC protocol.
-    let mut connection = Connection::new()
-        .context("Failed to connect to sway IPC. Is sway running?")?;
-
-    // 2. Query Compositor State
-    // Synchronously fetch the list of all active workspaces.
-    let workspaces = connection.get_workspaces()
-        .context("Failed to fetch workspaces")?;
-
-    // 3. Filter & Extract
-    // Use a functional iterator chain to find the single workspace marked as focused.
-    let focused_name = workspaces
-        .into_iter()
-        .find(|ws| ws.focused)               // Predicate: Is this the active one?
-        .map(|ws| ws.name)                   // Transform: I only care about the name string
-        .unwrap_or_else(|| "?".to_string()); // Fallback for transient states (e.g. during startup)
-    // 4. Output
+/// How long to wait before trying to reach the compositor again after the IPC socket goes away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Placeholder shown while no workspace is focused or the compositor is unreachable.
+const FALLBACK_NAME: &str = "?";
+
+/// Compositor-agnostic view of a single workspace, filled in by each backend.
+#[derive(Serialize, Debug, Clone)]
+pub struct WorkspaceInfo {
+    pub name: String,
+    pub num: i32,        // Workspace number (-1 on sway for purely named workspaces)
+    pub output: String,  // Monitor the workspace lives on
+    pub focused: bool,   // Has keyboard focus
+    pub visible: bool,   // Currently shown on its output (focused or not)
+    pub urgent: bool,    // A window on it requested attention
+}
+
+/// Parsed command line flags for the query modes.
+struct Options {
+    watch: bool,
+    json_output: bool,
+    bar: bool,
+    output: Option<String>,
+}
+
+/// One-shot workspace actions.
+enum Action {
+    Switch(String),
+    MoveContainer { target: String, follow: bool },
+    Rename { old: Option<String>, new: String },
+    NextFree { move_container: bool },
+}
+
+/// What the user asked for on the command line.
+enum Command {
+    Query(Options),
+    Action(Action),
+    Autoname,
+}
+
+fn parse_action(name: &str, args: &[String]) -> Result<Action> {
+    let flags: Vec<&str> = args.iter().map(String::as_str).filter(|a| a.starts_with("--")).collect();
+    let values: Vec<String> = args.iter().filter(|a| !a.starts_with("--")).cloned().collect();
+    let allowed_flags: &[&str] = match name {
+        "move-container" => &["--follow"],
+        "next-free" => &["--move"],
+        _ => &[],
+    };
+    if let Some(flag) = flags.iter().find(|f| !allowed_flags.contains(f)) {
+        anyhow::bail!("Unknown flag '{}' for '{}'.", flag, name);
+    }
+
+    match (name, values.as_slice()) {
+        ("switch", [target]) => Ok(Action::Switch(target.clone())),
+        ("move-container", [target]) => Ok(Action::MoveContainer {
+            target: target.clone(),
+            follow: flags.contains(&"--follow"),
+        }),
+        ("rename", [new]) => Ok(Action::Rename { old: None, new: new.clone() }),
+        ("rename", [old, new]) => Ok(Action::Rename { old: Some(old.clone()), new: new.clone() }),
+        ("next-free", []) => Ok(Action::NextFree { move_container: flags.contains(&"--move") }),
+        ("switch", _) => anyhow::bail!("Usage: sway-workspace switch <WORKSPACE>"),
+        ("move-container", _) => anyhow::bail!("Usage: sway-workspace move-container <WORKSPACE> [--follow]"),
+        ("rename", _) => anyhow::bail!("Usage: sway-workspace rename [<OLD>] <NEW>"),
+        _ => anyhow::bail!("Usage: sway-workspace next-free [--move]"),
+    }
+}
+
+fn parse_args() -> Result<Command> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "autoname") {
+        if let Some(extra) = args.get(1) {
+            anyhow::bail!("Unexpected argument '{}' for 'autoname'.", extra);
+        }
+        return Ok(Command::Autoname);
+    }
+    if let Some(name) = args.first()
+        && matches!(name.as_str(), "switch" | "move-container" | "rename" | "next-free")
+    {
+        return Ok(Command::Action(parse_action(name, &args[1..])?));
+    }
+
+    let mut options = Options { watch: false, json_output: false, bar: false, output: None };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => options.watch = true,
+            "--json" => options.json_output = true,
+            "--bar" => options.bar = true,
+            "--output" => {
+                options.output = Some(args.next().context("--output requires a monitor name")?);
+            }
+            _ => anyhow::bail!(
+                "Unknown argument '{}'. Use --watch, --json, --bar, --output <NAME> or an action \
+                 (switch, move-container, rename, next-free, autoname).",
+                arg
+            ),
+        }
+    }
+    Ok(Command::Query(options))
+}
+
+// --- Backend Dispatch ---
+
+#[derive(Debug, Clone, Copy)]
+enum Compositor {
+    Sway,
+    Hyprland,
+    Niri,
+}
+
+/// Identifies the active Wayland compositor by checking unique environment variables.
+///
+/// I prioritize socket variables (e.g., `SWAYSOCK`) over `XDG_CURRENT_DESKTOP`
+/// because the latter is sometimes set incorrectly by display managers or previous sessions.
+fn get_compositor() -> Option<Compositor> {
+    if env::var("NIRI_SOCKET").is_ok() { return Some(Compositor::Niri); }
+    if env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() { return Some(Compositor::Hyprland); }
+    if env::var("SWAYSOCK").is_ok() { return Some(Compositor::Sway); }
+    // Fallback: Check standard XDG variables
+    if let Ok(desktop) = env::var("XDG_CURRENT_DESKTOP") {
+        let desktop = desktop.to_lowercase();
+        if desktop.contains("niri") { return Some(Compositor::Niri); }
+        if desktop.contains("hypr") { return Some(Compositor::Hyprland); }
+        if desktop.contains("sway") { return Some(Compositor::Sway); }
+    }
+    None
+}
+
+/// A connected query client for whichever compositor is running.
+enum Client {
+    Sway(sway::Client),
+    Hyprland(hyprland::Client),
+    Niri(niri::Client),
+}
+
+impl Client {
+    fn connect(compositor: Compositor) -> Result<Self> {
+        Ok(match compositor {
+            Compositor::Sway => Client::Sway(sway::Client::connect()?),
+            Compositor::Hyprland => Client::Hyprland(hyprland::Client::connect()?),
+            Compositor::Niri => Client::Niri(niri::Client::connect()?),
+        })
+    }
+
+    fn workspaces(&mut self) -> Result<Vec<WorkspaceInfo>> {
+        match self {
+            Client::Sway(c) => c.workspaces(),
+            Client::Hyprland(c) => c.workspaces(),
+            Client::Niri(c) => c.workspaces(),
+        }
+    }
+}
+
+/// Blocks on the compositor's event stream, calling `on_event` for every relevant change.
+fn wait_for_events(compositor: Compositor, on_event: impl FnMut() -> Result<()>) -> Result<()> {
+    match compositor {
+        Compositor::Sway => sway::wait_for_events(on_event),
+        Compositor::Hyprland => hyprland::wait_for_events(on_event),
+        Compositor::Niri => niri::wait_for_events(on_event),
+    }
+}
+
+// --- Rendering ---
+
+/// Escapes the characters Pango markup treats specially, since Waybar renders `text` as markup.
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Single-label mode: the focused workspace (or, with `--output`, the one shown on that monitor).
+fn render_focused(workspaces: &[WorkspaceInfo], options: &Options) -> String {
+    let name = workspaces
+        .iter()
+        .find(|ws| ws.focused)                                               // Predicate: Is this the active one?
+        .or_else(|| options.output.as_ref().and(workspaces.iter().find(|ws| ws.visible)))
+        .map(|ws| ws.name.as_str())                                          // Transform: I only care about the name string
+        .unwrap_or(FALLBACK_NAME);                                           // Fallback for transient states (e.g. during startup)
+
+    if options.json_output {
+        json!({
+            "text": name,
+            "tooltip": format!("Workspace: {}", name),
+            "class": if name == FALLBACK_NAME { "disconnected" } else { "focused" }
+        }).to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Workspace-strip mode: one Waybar object describing every workspace.
+///
+/// `text` is Pango markup (focused = bold, visible = underlined, urgent = italic) and
+/// `class` carries module-level states for CSS:
+/// - `focused`: the (filtered) output currently holds keyboard focus.
+/// - `urgent`:  at least one workspace needs attention.
+/// - `empty`:   nothing to show (e.g. the filtered monitor is gone).
+///
+/// The raw list is included under `workspaces` for scripts that want to do their own layout.
+fn render_bar(workspaces: &[WorkspaceInfo]) -> String {
+    let text = workspaces
+        .iter()
+        .map(|ws| {
+            let mut label = escape_markup(&ws.name);
+            if ws.urgent { label = format!("<i>{}</i>", label); }
+            if ws.visible && !ws.focused { label = format!("<u>{}</u>", label); }
+            if ws.focused { label = format!("<b>{}</b>", label); }
+            label
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let tooltip = workspaces
+        .iter()
+        .map(|ws| {
+            let mut states = Vec::new();
+            if ws.focused { states.push("focused"); }
+            if ws.visible { states.push("visible"); }
+            if ws.urgent { states.push("urgent"); }
+            if states.is_empty() {
+                format!("{} on {}", ws.name, ws.output)
+            } else {
+                format!("{} on {} [{}]", ws.name, ws.output, states.join(", "))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut classes = Vec::new();
+    if workspaces.iter().any(|ws| ws.focused) { classes.push("focused"); }
+    if workspaces.iter().any(|ws| ws.urgent) { classes.push("urgent"); }
+    if workspaces.is_empty() { classes.push("empty"); }
+
+    json!({
+        "text": text,
+        "tooltip": tooltip,
+        "class": classes,
+        "workspaces": workspaces,
+    }).to_string()
+}
+
+/// Turns the compositor state into the exact line printed for Waybar.
+fn render(mut workspaces: Vec<WorkspaceInfo>, options: &Options) -> String {
+    if let Some(output) = &options.output {
+        workspaces.retain(|ws| &ws.output == output);
+    }
+    if options.bar {
+        // Numbered workspaces first, in order; named-only ones (num -1) by name.
+        workspaces.sort_by(|a, b| (a.num < 0, a.num, &a.name).cmp(&(b.num < 0, b.num, &b.name)));
+        render_bar(&workspaces)
+    } else {
+        render_focused(&workspaces, options)
+    }
+}
+
+/// Line shown while the compositor is unreachable.
+fn render_disconnected(options: &Options) -> String {
+    if options.bar {
+        json!({ "text": FALLBACK_NAME, "class": ["disconnected"], "workspaces": [] }).to_string()
+    } else {
+        render_focused(&[], options)
+    }
+}
+
+/// Prints a line unless it is identical to the previous one.
+/// Compositors fire several events per switch (focus, output, create), so I only
+/// write when the rendered output actually changes.
+/// Rust's stdout is line-buffered, so each line reaches Waybar as soon as it is written.
+fn emit_if_changed(line: String, last: &mut Option<String>) {
+    if last.as_deref() != Some(line.as_str()) {
+        println!("{}", line);
+        *last = Some(line);
+    }
+}
+
+// --- Modes ---
+
+/// Runs one connected session: prints the current state, then follows the event stream
+/// until the compositor goes away (returns Ok) or the socket errors out (returns Err).
+fn stream_events(compositor: Compositor, options: &Options, last: &mut Option<String>) -> Result<()> {
+    let mut client = Client::connect(compositor)?;
+    emit_if_changed(render(client.workspaces()?, options), last);
+
+    // Re-query instead of trusting event payloads: not every event carries workspace data,
+    // and this keeps a single source of truth for the label across backends.
+    wait_for_events(compositor, || {
+        emit_if_changed(render(client.workspaces()?, options), last);
+        Ok(())
+    })
+}
+
+/// Event-driven mode for Waybar.
+/// Never returns: if the compositor restarts (or the session is still starting up), I show
+/// the fallback label and keep retrying until the IPC socket comes back.
+fn run_watch(compositor: Compositor, options: &Options) -> Result<()> {
+    let mut last: Option<String> = None;
+    loop {
+        match stream_events(compositor, options, &mut last) {
+            Ok(()) => eprintln!("sway-workspace: event stream closed, reconnecting..."),
+            Err(e) => eprintln!("sway-workspace: {:#}, reconnecting...", e),
+        }
+        emit_if_changed(render_disconnected(options), &mut last);
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+/// Original one-shot behaviour: print once and exit.
+fn run_once(compositor: Compositor, options: &Options) -> Result<()> {
+    let mut client = Client::connect(compositor)?;
     // Print strictly to stdout so this binary can be used as a `custom/script` source in Waybar.
-    println!("{}", focused_name);
-    
+    println!("{}", render(client.workspaces()?, options));
     Ok(())
 }
+
+/// Executes a workspace action through the sway IPC connection.
+fn run_action(compositor: Compositor, action: Action) -> Result<()> {
+    let Client::Sway(mut client) = Client::connect(compositor)? else {
+        anyhow::bail!("Workspace actions are only supported on Sway.");
+    };
+    match action {
+        Action::Switch(target) => client.switch_to(&target),
+        Action::MoveContainer { target, follow } => client.move_container_to(&target, follow),
+        Action::Rename { old, new } => client.rename(old.as_deref(), &new),
+        Action::NextFree { move_container } => {
+            let target = client.next_free_number()?.to_string();
+            if move_container {
+                client.move_container_to(&target, true)
+            } else {
+                client.switch_to(&target)
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let command = parse_args()?;
+    let compositor = get_compositor()
+        .context("No supported compositor detected (expected Sway, Hyprland or Niri).")?;
+
+    match command {
+        Command::Action(action) => run_action(compositor, action),
+        Command::Autoname => match compositor {
+            Compositor::Sway => autoname::run(),
+            _ => anyhow::bail!("Automatic workspace naming is only supported on Sway."),
+        },
+        Command::Query(options) if options.watch => run_watch(compositor, &options),
+        Command::Query(options) => run_once(compositor, &options),
+    }
+}
@@ -0,0 +1,108 @@
+//! Niri backend.
+//!
+//! Niri listens on the Unix socket in `$NIRI_SOCKET`. Each request is a single line
+//! of JSON and the reply is `{"Ok": ...}` or `{"Err": "..."}`. Sending `"EventStream"`
+//! turns the connection into a stream of one JSON event per line.
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::WorkspaceInfo;
+
+// --- IPC Structures ---
+// These match the `Workspaces` reply of `niri msg --json workspaces`.
+#[derive(Deserialize, Debug)]
+struct NiriWorkspace {
+    idx: u8,
+    name: Option<String>,
+    output: Option<String>,
+    is_active: bool,
+    is_focused: bool,
+    #[serde(default)] // Only reported by niri >= 25.05
+    is_urgent: bool,
+}
+
+#[derive(Deserialize, Debug)]
+enum NiriReply<T> {
+    Ok(T),
+    Err(String),
+}
+
+#[derive(Deserialize, Debug)]
+enum NiriResponse {
+    Workspaces(Vec<NiriWorkspace>),
+}
+
+/// Opens a fresh connection and sends one request line.
+fn open(request: &str) -> Result<BufReader<UnixStream>> {
+    let path = env::var("NIRI_SOCKET")
+        .context("NIRI_SOCKET is not set. Is niri running?")?;
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to niri socket {}", path))?;
+    stream.write_all(format!("{}\n", request).as_bytes())?;
+    stream.flush()?;
+    Ok(BufReader::new(stream))
+}
+
+/// Reads the single-line reply that follows every request.
+fn read_reply<T: for<'de> Deserialize<'de>>(reader: &mut BufReader<UnixStream>) -> Result<T> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match serde_json::from_str::<NiriReply<T>>(&line).context("Failed to parse niri reply")? {
+        NiriReply::Ok(value) => Ok(value),
+        NiriReply::Err(e) => Err(anyhow!("niri returned an error: {}", e)),
+    }
+}
+
+pub struct Client;
+
+impl Client {
+    pub fn connect() -> Result<Self> {
+        // Niri expects one request per connection, so there is nothing to keep open.
+        env::var("NIRI_SOCKET").context("NIRI_SOCKET is not set. Is niri running?")?;
+        Ok(Self)
+    }
+
+    pub fn workspaces(&mut self) -> Result<Vec<WorkspaceInfo>> {
+        let mut reader = open("\"Workspaces\"")?;
+        let NiriResponse::Workspaces(workspaces) = read_reply(&mut reader)?;
+        Ok(workspaces
+            .into_iter()
+            .map(|ws| WorkspaceInfo {
+                // Unnamed niri workspaces are identified by their index on the output.
+                name: ws.name.unwrap_or_else(|| ws.idx.to_string()),
+                num: i32::from(ws.idx),
+                output: ws.output.unwrap_or_default(),
+                focused: ws.is_focused,
+                // `is_active` means "currently shown on its output".
+                visible: ws.is_active,
+                urgent: ws.is_urgent,
+            })
+            .collect())
+    }
+}
+
+/// Follows niri's event stream and calls `on_event` for every workspace change.
+/// Returns Ok when niri closes the socket.
+pub fn wait_for_events(mut on_event: impl FnMut() -> Result<()>) -> Result<()> {
+    let mut reader = open("\"EventStream\"")?;
+    // The first line acknowledges the request ({"Ok":"Handled"}).
+    let _: Value = read_reply(&mut reader)?;
+
+    for line in reader.lines() {
+        let line = line.context("niri event stream failed")?;
+        let event: Value = serde_json::from_str(&line).context("Failed to parse niri event")?;
+        // Events are externally tagged: {"WorkspaceActivated": {...}}
+        let is_workspace_event = event
+            .as_object()
+            .and_then(|obj| obj.keys().next())
+            .is_some_and(|name| name.starts_with("Workspace"));
+        if is_workspace_event {
+            on_event()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Sway backend.
+//!
+//! Talks to sway over the `$SWAYSOCK` JSON-IPC socket via the `swayipc` crate.
+
+use anyhow::{Context, Result};
+use swayipc::{Connection, EventType, Node};
+use crate::WorkspaceInfo;
+
+/// A request/reply connection used for state queries.
+pub struct Client {
+    connection: Connection,
+}
+
+impl Client {
+    pub fn connect() -> Result<Self> {
+        let connection = Connection::new()
+            .context("Failed to connect to sway IPC. Is sway running?")?;
+        Ok(Self { connection })
+    }
+
+    /// Synchronously fetches the list of all active workspaces.
+    pub fn workspaces(&mut self) -> Result<Vec<WorkspaceInfo>> {
+        let workspaces = self.connection.get_workspaces()
+            .context("Failed to fetch workspaces")?;
+        Ok(workspaces
+            .into_iter()
+            .map(|ws| WorkspaceInfo {
+                name: ws.name,
+                num: ws.num,
+                output: ws.output,
+                focused: ws.focused,
+                visible: ws.visible,
+                urgent: ws.urgent,
+            })
+            .collect())
+    }
+
+    /// Fetches the full container tree (outputs -> workspaces -> windows).
+    pub fn tree(&mut self) -> Result<Node> {
+        self.connection.get_tree().context("Failed to fetch sway tree")
+    }
+
+    // --- Actions ---
+
+    /// Runs a sway command and surfaces the first failure reported by sway.
+    fn run_command(&mut self, command: &str) -> Result<()> {
+        let outcomes = self.connection.run_command(command)
+            .with_context(|| format!("Failed to send sway command: {}", command))?;
+        for outcome in outcomes {
+            outcome.with_context(|| format!("Sway rejected command: {}", command))?;
+        }
+        Ok(())
+    }
+
+    /// Focuses a workspace, creating it if needed.
+    pub fn switch_to(&mut self, target: &str) -> Result<()> {
+        self.run_command(&format!("workspace {}", workspace_criteria(target)))
+    }
+
+    /// Moves the focused container to a workspace, optionally following it there.
+    pub fn move_container_to(&mut self, target: &str, follow: bool) -> Result<()> {
+        self.run_command(&format!("move container to workspace {}", workspace_criteria(target)))?;
+        if follow {
+            self.switch_to(target)?;
+        }
+        Ok(())
+    }
+
+    /// Renames a workspace (the focused one when `old` is None).
+    /// A numeric `old` is resolved to the workspace's full name, so "3" also matches "3: web".
+    pub fn rename(&mut self, old: Option<&str>, new: &str) -> Result<()> {
+        let command = match old {
+            Some(old) => {
+                let old_name = match old.parse::<i32>() {
+                    Ok(num) => self.workspaces()?
+                        .into_iter()
+                        .find(|ws| ws.num == num)
+                        .map(|ws| ws.name)
+                        .with_context(|| format!("No workspace with number {}", num))?,
+                    Err(_) => old.to_string(),
+                };
+                format!("rename workspace {} to {}", quote(&old_name), quote(new))
+            }
+            None => format!("rename workspace to {}", quote(new)),
+        };
+        self.run_command(&command)
+    }
+
+    /// Returns the lowest workspace number (starting at 1) that has no workspace yet.
+    pub fn next_free_number(&mut self) -> Result<i32> {
+        let used: Vec<i32> = self.workspaces()?.into_iter().map(|ws| ws.num).collect();
+        Ok((1..).find(|n| !used.contains(n)).unwrap_or(1))
+    }
+}
+
+/// Wraps a value in double quotes for sway's command parser.
+/// I build commands from typed values here so callers never concatenate raw strings.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Numbers use `number N` so they match auto-named workspaces like "3: web".
+fn workspace_criteria(target: &str) -> String {
+    match target.parse::<i32>() {
+        Ok(num) => format!("number {}", num),
+        Err(_) => quote(target),
+    }
+}
+
+/// Blocks on sway's event stream and calls `on_event` for every workspace/output change.
+/// Returns Ok when sway closes the stream (e.g. on exit or restart).
+pub fn wait_for_events(mut on_event: impl FnMut() -> Result<()>) -> Result<()> {
+    // A subscribed connection can only receive events, so queries must go through
+    // a separate `Client` owned by the caller.
+    let events = Connection::new()
+        .context("Failed to open sway IPC event connection")?
+        .subscribe([EventType::Workspace, EventType::Output])
+        .context("Failed to subscribe to sway events")?;
+
+    for event in events {
+        event.context("Sway IPC event stream failed")?;
+        on_event()?;
+    }
+    Ok(())
+}
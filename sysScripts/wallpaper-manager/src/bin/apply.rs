@@ -3,20 +3,42 @@
 //! A specialized utility responsible for the side-effects of changing the desktop background.
 //! It abstracts away the differences between Wayland compositors (Hyprland, Sway, Niri)
 //! so the selection tool doesn't need to know the implementation details.
+//!
+//! Videos and GIFs go to `mpvpaper` on every compositor, one player per output, on top of
+//! whatever image backend the session uses. Applying an image to an output stops its player.
+//!
+//! On Sway a single `swaybg` draws every output; each change replaces it with a new one built
+//! from the recorded state, so the other monitors keep their wallpapers.
+//!
+//! Usage:
+//!   wp-apply <file> <compositor> <monitor> [fit]  => Set one output (fit: fill, fit, stretch, center, tile).
+//!   wp-apply --restore                            => Re-apply every output's last wallpaper (session start).
+//!   wp-apply --theme <file>                       => Only regenerate the theme from <file>.
+//!
+//! Every successful apply is recorded per compositor and output (see `wallpaper_manager::state`),
+//! and, with `[wallpaper_manager.theme]` enabled, re-themes the desktop from the new wallpaper.
 
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 use anyhow::{Context, Result};
 use std::fs;
 use serde::Deserialize;
+use wallpaper_manager::library::Library;
+use wallpaper_manager::monitors::{get_compositor, get_monitor_list};
+use wallpaper_manager::state::{self, Applied, Fit};
+use wallpaper_manager::theme::{self, ThemeConfig};
+use wallpaper_manager::video;
 
 /// Resolves shell-style paths (e.g., "~/Pictures") to absolute system paths.
 fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(stripped);
-        }
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
     }
     PathBuf::from(path)
 }
@@ -25,11 +47,26 @@ fn expand_path(path: &str) -> PathBuf {
 #[allow(dead_code)]
 struct WallpaperManagerConfig {
     swww_params: Vec<String>,        // Transition effects for swww
-    swaybg_cache_file: String,       // Where Sway stores its current state
-    hyprland_refresh_script: String, // Hook to reload Hyprland colors (e.g., Pywal)
+    swaybg_cache_file: String,       // Legacy single-wallpaper Sway cache; only imported once by --restore
+    hyprland_refresh_script: String, // Hook to reload Hyprland colors (e.g., Pywal); skipped when `theme` is enabled
     wallpaper_dir: String,
+    cache_file: String,              // wp-daemon's index; the library (history) sits beside it
+    // Video wallpapers (mpvpaper)
+    #[serde(default = "default_mpv_options")]
+    mpv_options: String,                        // mpv options, space separated, without "--"
+    #[serde(default)]
+    output_mpv_options: HashMap<String, String>, // Per-output replacement for mpv_options
+    #[serde(default = "default_pause_video_on_battery")]
+    pause_video_on_battery: bool,               // Start paused when unplugged (wp-daemon handles changes)
+    #[serde(default)]
+    default_fit: Fit,                           // When wp-apply isn't given one
+    #[serde(default)]
+    theme: ThemeConfig,                         // Built-in palette templates
 }
 
+fn default_mpv_options() -> String { "no-audio loop hwdec=auto".to_string() }
+fn default_pause_video_on_battery() -> bool { true }
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     wallpaper_manager: WallpaperManagerConfig,
@@ -54,14 +91,21 @@ fn pkill(name: &str) {
     Command::new("pkill").arg("-x").arg(name).status().ok();
 }
 
+/// PIDs of the running processes named exactly `name`.
+fn pids_of(name: &str) -> Vec<u32> {
+    Command::new("pgrep").arg("-x").arg(name).output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).lines().filter_map(|l| l.trim().parse().ok()).collect())
+        .unwrap_or_default()
+}
+
 // --- Compositor Strategies ---
 
 /// Applies wallpaper using `swww` (Solution for Hyprland/Niri).
 /// Supports animated transitions and per-monitor namespaces.
-fn apply_swww_wallpaper(selected_file: &Path, monitor: &str, namespace: &str, swww_params: &[String]) -> Result<()> {
+fn apply_swww_wallpaper(selected_file: &Path, monitor: &str, fit: Fit, namespace: &str, swww_params: &[String]) -> Result<()> {
     println!("Applying wallpaper via swww (namespace: {})...", namespace);
-    // Clean up incompatible daemons
-    pkill("mpvpaper");
+    // Clean up incompatible daemons (and this output's video, which would cover the image)
+    video::stop(monitor);
     pkill("swaybg");
     // Ensure the daemon is running in the background
     let _ = Command::new("swww-daemon")
@@ -73,70 +117,275 @@ fn apply_swww_wallpaper(selected_file: &Path, monitor: &str, namespace: &str, sw
     // Wait briefly for daemon startup race conditions
     std::thread::sleep(std::time::Duration::from_millis(100));
     // Send the image command
-    Command::new("swww")
+    let status = Command::new("swww")
         .arg("img") 
         .arg("--namespace")
         .arg(namespace)
         .arg("-o")
         .arg(monitor)
+        .arg("--resize")
+        .arg(fit.swww_resize())
         .arg(selected_file)
         .args(swww_params)
         .status()
         .context("swww img command failed")?;
+    // Checked so a failed apply isn't recorded as the output's wallpaper.
+    anyhow::ensure!(status.success(), "swww img failed for {}", monitor);
     Ok(())
 }
-/// Applies wallpaper using `swaybg` (Solution for Sway).
-/// Swaybg is static and requires manual process management.
-fn apply_sway_wallpaper(selected_file: &Path, monitor: &str, cache_filename: &str) -> Result<()> {
-    println!("Applying wallpaper for Sway...");
+/// Applies wallpapers using `swaybg` (Sway), one instance for every output:
+/// `swaybg -o <output> -i <image> -m <mode> -o ...`, built from the recorded state.
+/// The previous instance (and strays from older versions, which started one per output) is
+/// only killed after the new one has drawn, so changing one monitor never blanks the others.
+fn apply_sway_wallpapers(outputs: &BTreeMap<String, Applied>) -> Result<()> {
+    println!("Applying wallpapers for Sway...");
     // Kill swww as it conflicts with swaybg
     pkill("swww-daemon");
     pkill("hyprpaper");
-    Command::new("swaybg")
+    let previous = pids_of("swaybg");
+
+    let mut swaybg = Command::new("swaybg");
+    let mut drawn = 0;
+    for (output, applied) in outputs {
+        // Video outputs belong to mpvpaper; deleted files would make swaybg exit
+        if video::is_video(&applied.path) || !applied.path.exists() {
+            continue;
+        }
+        swaybg.arg("-o").arg(output)
+            .arg("-i").arg(&applied.path)
+            .arg("-m").arg(applied.fit.swaybg_mode());
+        drawn += 1;
+    }
+    if drawn == 0 {
+        return Ok(());
+    }
+    let mut child = swaybg.stdin(Stdio::null()).stdout(Stdio::null()).spawn().context("Failed to run swaybg")?;
+    wait_until_drawn(&mut child)?;
+
+    for pid in previous {
+        Command::new("kill").arg(pid.to_string()).status().ok();
+    }
+    Ok(())
+}
+
+/// Waits for a new swaybg to be on screen. swaybg only maps its shared-memory buffers once the
+/// compositor has configured its surfaces, so they show up in /proc/<pid>/maps when it has drawn.
+/// Fails if swaybg exits (bad image), gives up waiting after a few seconds.
+fn wait_until_drawn(child: &mut Child) -> Result<()> {
+    let maps = format!("/proc/{}/maps", child.id());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("swaybg exited before drawing ({})", status);
+        }
+        let drawn = fs::read_to_string(&maps).is_ok_and(|maps| {
+            maps.lines().any(|line| line.contains("/dev/shm/swaybg") || line.contains("/memfd:"))
+        });
+        if drawn {
+            // Mapped right before the commit; give the compositor a frame to show it
+            thread::sleep(Duration::from_millis(50));
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    eprintln!("swaybg hasn't drawn after 5s, replacing the old one anyway");
+    Ok(())
+}
+
+/// Plays a video (or GIF) on one output with `mpvpaper`. Works the same on every compositor:
+/// mpvpaper is a plain layer-shell client. The image backend keeps running underneath.
+fn apply_video_wallpaper(selected_file: &Path, monitor: &str, fit: Fit, config: &WallpaperManagerConfig) -> Result<()> {
+    println!("Applying video wallpaper via mpvpaper on {}...", monitor);
+    // One player per output: replace only this output's.
+    video::stop(monitor);
+
+    let mut options = config.output_mpv_options.get(monitor).unwrap_or(&config.mpv_options).clone();
+    options.push(' ');
+    options.push_str(fit.mpv_options());
+    // The socket is how wp-daemon (battery) and the next wp-apply (replace) find this player.
+    options.push_str(&format!(" input-ipc-server={}", video::ipc_socket(monitor).display()));
+    if config.pause_video_on_battery && video::on_battery() {
+        options.push_str(" pause");
+    }
+
+    Command::new("mpvpaper")
         .arg("-o")
+        .arg(options.trim())
         .arg(monitor)
-        .arg("-i")
         .arg(selected_file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
         .spawn()
-        .context("Failed to run swaybg")?;
+        .context("Failed to run mpvpaper")?;
+    Ok(())
+}
+
+/// Sets `wallpaper` on one output through the backend for `compositor`.
+fn apply(wallpaper_path: &Path, compositor: &str, monitor: &str, fit: Fit, config: &WallpaperManagerConfig) -> Result<()> {
+    // Videos bypass the image backends entirely
+    if video::is_video(wallpaper_path) {
+        return apply_video_wallpaper(wallpaper_path, monitor, fit, config);
+    }
+
+    // Strategy Pattern: Dispatch based on the detected environment
+    match compositor {
+        "hyprland" => {
+            apply_swww_wallpaper(wallpaper_path, monitor, fit, "hypr", &config.swww_params)?;
+            // Trigger hook to update system colors (e.g. Waybar styles), unless we theme ourselves
+            if !config.theme.enabled {
+                let refresh_script = expand_path(&config.hyprland_refresh_script);
+                Command::new("bash").arg(refresh_script).status()?;
+            }
+        }
+        "niri" => {
+            // Niri uses the same backend (swww) but a isolated namespace
+            apply_swww_wallpaper(wallpaper_path, monitor, fit, "niri", &config.swww_params)?;
+        }
+        "sway" => {
+            // This output's video would cover the image
+            video::stop(monitor);
+            let mut outputs = state::load().remove("sway").unwrap_or_default();
+            outputs.insert(monitor.to_string(), Applied { path: wallpaper_path.to_path_buf(), fit });
+            apply_sway_wallpapers(&outputs)?;
+        }
+        _ => anyhow::bail!("Compositor argument '{}' is not recognized.", compositor),
+    }
+    Ok(())
+}
 
-    // Cache the selection so Sway can restore it on reboot (handled by external startup scripts)
-    if let Some(mut cache_path) = dirs::cache_dir() {
-        cache_path.push(cache_filename);
-        let _ = fs::write(cache_path, selected_file.to_str().unwrap_or(""));
+/// Adds `paths` to wp-select's "recent" history. Recorded here rather than in the picker, since
+/// every change (wp-select, wp-rotate, --restore) goes through wp-apply. Only reported on failure:
+/// the wallpaper is already set.
+fn remember(paths: &[&Path], config: &WallpaperManagerConfig) {
+    let cache_file = expand_path(&config.cache_file);
+    let result = Library::update(&cache_file, |library| {
+        for path in paths {
+            library.touch(path);
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to record history: {:#}", e);
+    }
+}
+
+/// Re-applies the last wallpaper of every output recorded for the running compositor.
+/// Meant for session startup; outputs that aren't connected and files that were deleted
+/// are skipped, only real failures make it exit non-zero.
+fn restore(config: &WallpaperManagerConfig) -> Result<()> {
+    let compositor = get_compositor();
+    if compositor == "unknown" {
+        anyhow::bail!("No supported compositor running.");
+    }
+    let mut state = state::load();
+    if compositor == "sway" && !state.contains_key("sway") {
+        import_swaybg_cache(&config.swaybg_cache_file)?;
+        state = state::load();
     }
+    let Some(outputs) = state.get(&compositor) else {
+        println!("No wallpapers recorded for {} yet.", compositor);
+        return Ok(());
+    };
 
+    // If the compositor can't be asked, every recorded output is tried.
+    let connected = match get_monitor_list(&compositor) {
+        Ok(list) => Some(list),
+        Err(e) => {
+            eprintln!("Could not list outputs, trying all of them: {:#}", e);
+            None
+        }
+    };
+
+    let mut failed = 0;
+    let mut restored: Vec<&Path> = Vec::new();
+    for (output, applied) in outputs {
+        // Sway's images all go into one swaybg, below
+        if compositor == "sway" && !video::is_video(&applied.path) {
+            continue;
+        }
+        if let Some(connected) = &connected
+            && !connected.contains(output)
+        {
+            println!("{}: not connected, skipping", output);
+            continue;
+        }
+        if !applied.path.exists() {
+            eprintln!("{}: {} no longer exists, skipping", output, applied.path.display());
+            continue;
+        }
+        match apply(&applied.path, &compositor, output, applied.fit, config) {
+            Ok(()) => restored.push(&applied.path),
+            Err(e) => {
+                eprintln!("{}: {:#}", output, e);
+                failed += 1;
+            }
+        }
+    }
+    // swaybg keeps the disconnected outputs' entries and draws them when they come back
+    if compositor == "sway" {
+        match apply_sway_wallpapers(outputs) {
+            Ok(()) => restored.extend(outputs.values()
+                .map(|a| a.path.as_path())
+                .filter(|p| !video::is_video(p) && p.exists())),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    remember(&restored, config);
+    anyhow::ensure!(failed == 0, "{} output(s) could not be restored", failed);
     Ok(())
 }
 
+/// Older versions kept one Sway wallpaper for all outputs in `swaybg_cache_file`. Carries it
+/// over as the "*" output (swaybg's fallback for outputs without their own entry).
+fn import_swaybg_cache(cache_filename: &str) -> Result<()> {
+    let Some(cache_path) = dirs::cache_dir().map(|dir| dir.join(cache_filename)) else { return Ok(()) };
+    let Ok(path) = fs::read_to_string(&cache_path) else { return Ok(()) };
+    let path = PathBuf::from(path.trim());
+    if path.as_os_str().is_empty() {
+        return Ok(());
+    }
+    println!("Importing the Sway wallpaper from {}", cache_path.display());
+    state::record("sway", "*", Applied { path, fit: Fit::Fill })
+}
+
 fn main() -> Result<()> {
     let global_config = load_config()?;
     let config = global_config.wallpaper_manager;
-    // Parse CLI arguments passed by `wp-select`
     let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--restore") {
+        return restore(&config);
+    }
+    if args.get(1).map(String::as_str) == Some("--theme") {
+        let wallpaper_path = args.get(2).context("Usage: wp-apply --theme <file>")?;
+        let wallpaper_path = fs::canonicalize(wallpaper_path).with_context(|| format!("Cannot find {}", wallpaper_path))?;
+        return theme::generate(&config.theme, &wallpaper_path);
+    }
+
+    // Parse CLI arguments passed by `wp-select`
     let wallpaper_path_str = args.get(1).context("Missing wallpaper path")?;
     let compositor = args.get(2).context("Missing compositor name")?;
     let monitor = args.get(3).context("Missing monitor name")?;
+    let fit = match args.get(4) {
+        Some(fit) => fit.parse()?,
+        None => config.default_fit,
+    };
 
-    let wallpaper_path = PathBuf::from(wallpaper_path_str);
+    // Absolute, so `--restore` works from any working directory, but not canonical: callers pass
+    // the index's paths, and the history (also written by `--restore` from this state) has to
+    // match them even when `wallpaper_dir` sits behind a symlink.
+    let path = std::path::absolute(wallpaper_path_str).unwrap_or_else(|_| PathBuf::from(wallpaper_path_str));
+    apply(&path, compositor, monitor, fit, &config)?;
 
-    // Strategy Pattern: Dispatch based on the detected environment
-    match compositor.as_str() {
-        "hyprland" => {
-            apply_swww_wallpaper(&wallpaper_path, monitor, "hypr", &config.swww_params)?;
-            // Trigger hook to update system colors (e.g. Waybar styles)
-            let refresh_script = expand_path(&config.hyprland_refresh_script);
-            Command::new("bash").arg(refresh_script).status()?;
-        }
-        "niri" => {
-            // Niri uses the same backend (swww) but a isolated namespace
-            apply_swww_wallpaper(&wallpaper_path, monitor, "niri", &config.swww_params)?;
-        }
-        "sway" => {
-            apply_sway_wallpaper(&wallpaper_path, monitor, &config.swaybg_cache_file)?;
-        }
-        _ => anyhow::bail!("Compositor argument '{}' is not recognized.", compositor),
-    }
+    remember(&[&path], &config);
+    state::record(compositor, monitor, Applied { path: path.clone(), fit })?;
 
+    // The wallpaper is set either way; a theme failure is only reported
+    if config.theme.enabled
+        && let Err(e) = theme::generate(&config.theme, &path)
+    {
+        eprintln!("Theme generation failed: {:#}", e);
+    }
     Ok(())
 }
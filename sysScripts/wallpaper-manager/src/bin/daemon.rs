@@ -3,25 +3,38 @@
 //! A background service that monitors the wallpaper directory.
 //! 1. Scans for new images recursively.
 //! 2. Generates thumbnails in parallel (using Rayon) to offload CPU work.
-//! 3. Maintains a JSON cache for the selection tool to read instantly.
+//!    Videos and GIFs get a poster frame (ffmpeg for videos) and are marked `video` in the index.
+//! 3. Maintains a JSON cache for the selection tool to read instantly, with each file's
+//!    dimensions, orientation, size, mtime and dominant color (for filtering and sorting).
 //! 4. Uses `notify` to watch for filesystem changes in real-time.
+//!
+//! After the initial scan the index lives in memory. Filesystem events are collected until
+//! the folder has been quiet for `reindex_debounce_ms`, then only the touched paths are
+//! re-indexed and the cache file is rewritten (atomically) once for the whole batch.
+//!
+//! With `pause_video_on_battery`, it also pauses every mpvpaper player while unplugged.
 
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
-use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use anyhow::{Context, Result};
 use image::imageops::FilterType;
-use notify::{RecursiveMode, Watcher};
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{EventKind, RecursiveMode, Watcher};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
+use wallpaper_manager::{palette, video};
 
 fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(stripped);
-        }
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
     }
     PathBuf::from(path)
 }
@@ -36,8 +49,15 @@ struct WallpaperManagerConfig {
     cache_file: String,
     rofi_config_path: String,
     rofi_theme_override: String,
+    #[serde(default = "default_reindex_debounce_ms")]
+    reindex_debounce_ms: u64, // Quiet time before a burst of file events is indexed
+    #[serde(default = "default_pause_video_on_battery")]
+    pause_video_on_battery: bool,
 }
 
+fn default_reindex_debounce_ms() -> u64 { 750 }
+fn default_pause_video_on_battery() -> bool { true }
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     wallpaper_manager: WallpaperManagerConfig,
@@ -60,87 +80,350 @@ struct Wallpaper {
     name: String,
     path: PathBuf,
     thumb_path: PathBuf,
+    thumb_key: String, // Hash of canonical path + size + mtime; names the thumbnail file
+    #[serde(default)]
+    video: bool,       // Played by mpvpaper; thumb_path is a poster frame
+    #[serde(default)]
+    meta: Option<Meta>, // None in caches written by older versions
+}
+
+/// What wp-select filters and sorts by. Reused as long as the file's `thumb_key` is the same.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Meta {
+    width: u32,             // Pixels (0 if unknown)
+    height: u32,
+    aspect: f64,            // width / height
+    orientation: Orientation,
+    file_size: u64,         // Bytes
+    modified: u64,          // Unix seconds
+    dominant_color: String, // "#rrggbb" (empty if unknown)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Orientation {
+    Landscape,
+    Portrait,
+    Square, // Within 5%
+    Unknown,
+}
+
+/// Reads a file's metadata. The dominant color comes from the thumbnail: same answer, a
+/// fraction of the decoding.
+fn read_meta(original_path: &Path, thumb_path: &Path) -> Meta {
+    let dimensions = if video::needs_ffmpeg(original_path) {
+        video::dimensions(original_path)
+    } else {
+        image::image_dimensions(original_path).ok() // Header only, no decoding
+    };
+    let (width, height) = dimensions.unwrap_or((0, 0));
+    let aspect = if height > 0 { width as f64 / height as f64 } else { 0.0 };
+    let orientation = if aspect == 0.0 {
+        Orientation::Unknown
+    } else if aspect > 1.05 {
+        Orientation::Landscape
+    } else if aspect < 0.95 {
+        Orientation::Portrait
+    } else {
+        Orientation::Square
+    };
+    let file_meta = fs::metadata(original_path).ok();
+    let modified = file_meta.as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let dominant_color = image::open(thumb_path).ok()
+        .and_then(|thumb| palette::dominant(&thumb))
+        .map(|color| color.to_string())
+        .unwrap_or_default();
+    Meta {
+        width,
+        height,
+        aspect,
+        orientation,
+        file_size: file_meta.map_or(0, |m| m.len()),
+        modified,
+        dominant_color,
+    }
+}
+
+/// Metadata already computed, by thumb_key.
+type KnownMeta = HashMap<String, Meta>;
+
+fn known_meta<'a>(wallpapers: impl Iterator<Item = &'a Wallpaper>) -> KnownMeta {
+    wallpapers
+        .filter_map(|w| Some((w.thumb_key.clone(), w.meta.clone()?)))
+        .collect()
 }
 const THUMB_WIDTH: u32 = 500;
 
-/// Generates a thumbnail for a given image if it doesn't exist.
-/// Returns the path to the thumbnail.
-fn ensure_thumbnail(original_path: &Path, thumb_dir: &Path) -> Option<PathBuf> {
-    let file_name = original_path.file_name()?;
-    let thumb_path = thumb_dir.join(file_name);
-    // Cache Hit: If thumbnail exists, skip processing to save CPU/Battery.
+/// FNV-1a: tiny, dependency-free, and (unlike `DefaultHasher`) stable across Rust releases,
+/// so a toolchain update doesn't invalidate every thumbnail.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Identifies one *version* of one file: two `sunset.jpg` in different folders get different
+/// keys, and editing a file in place (new size/mtime) gets a new key, so a stale thumbnail
+/// is never reused.
+fn thumb_key(original_path: &Path) -> Option<String> {
+    let canonical = fs::canonicalize(original_path).ok()?;
+    let meta = fs::metadata(&canonical).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let id = format!("{}\0{}\0{}.{}", canonical.display(), meta.len(), mtime.as_secs(), mtime.subsec_nanos());
+    Some(format!("{:016x}", fnv1a(id.as_bytes())))
+}
+
+/// Generates a thumbnail for a given image if there isn't one for this version of it.
+/// Returns the key and the path to the thumbnail.
+fn ensure_thumbnail(original_path: &Path, thumb_dir: &Path) -> Option<(String, PathBuf)> {
+    let key = thumb_key(original_path)?;
+    // Keep the source extension for images: the image crate picks the output format from it.
+    // Videos and GIFs get a static JPEG poster.
+    let ext = if video::is_video(original_path) {
+        "jpg".to_string()
+    } else {
+        original_path.extension()?.to_string_lossy().to_lowercase()
+    };
+    let thumb_path = thumb_dir.join(format!("{}.{}", key, ext));
+    // Cache Hit: same path, size and mtime as last time, skip processing to save CPU/Battery.
     if thumb_path.exists() {
-        return Some(thumb_path);
+        return Some((key, thumb_path));
     }
     // Cache Miss: Generate thumbnail
-    let img = match image::open(original_path) {
-        Ok(img) => img,
-        Err(_) => return None, // Skip unreadable/corrupt files
+    // Write beside it and rename, so an interrupted save can't leave a truncated "hit" behind.
+    // A symlink and its target share the key and may be on two workers at once, so each
+    // write gets its own temp file; whichever rename lands last wins, both are complete.
+    static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = thumb_dir.join(format!("{}.tmp{}-{}.{}", key, std::process::id(), n, ext));
+    let saved = if video::needs_ffmpeg(original_path) {
+        video::poster_frame(original_path, &tmp_path, THUMB_WIDTH)
+    } else {
+        let img = match image::open(original_path) {
+            Ok(img) => img,
+            Err(_) => return None, // Skip unreadable/corrupt files
+        };
+        // Resize using Nearest Neighbor for speed, or Lanczos3 for quality.
+        // Nearest is chosen here for performance on large directories.
+        let thumb = img.resize(THUMB_WIDTH, u32::MAX, FilterType::Nearest);
+        // JPEG has no alpha channel (a GIF's first frame is RGBA).
+        let thumb = if ext == "jpg" { image::DynamicImage::ImageRgb8(thumb.to_rgb8()) } else { thumb };
+        thumb.save(&tmp_path).map_err(anyhow::Error::from)
     };
-    // Resize using Nearest Neighbor for speed, or Lanczos3 for quality. 
-    // Nearest is chosen here for performance on large directories.
-    let thumb = img.resize(THUMB_WIDTH, u32::MAX, FilterType::Nearest);
-    if let Err(e) = thumb.save(&thumb_path) {
+    if let Err(e) = saved.and_then(|_| fs::rename(&tmp_path, &thumb_path).map_err(anyhow::Error::from)) {
         eprintln!("Failed to save thumb for {:?}: {}", original_path, e);
+        let _ = fs::remove_file(&tmp_path);
         return None;
     }
-    Some(thumb_path)
+    Some((key, thumb_path))
 }
-/// The core indexing logic.
-/// 1. Walks the directory.
-/// 2. Filters video files.
-/// 3. Generates thumbnails in parallel.
-/// 4. Writes the master JSON index.
-fn scan_and_update_cache(wall_dir: &Path, cache_file: &Path) -> Result<()> {
+/// The in-memory index, keyed by wallpaper path (sorted, so the cache file is stable).
+type Index = BTreeMap<PathBuf, Wallpaper>;
+
+fn thumb_dir() -> Result<PathBuf> {
     let home = dirs::home_dir().context("Failed to get $HOME")?;
     let thumb_dir = home.join(".cache/wallpaper_thumbs");
     fs::create_dir_all(&thumb_dir)?;
-    println!("Scanning wallpapers in {:?}...", wall_dir);
-    // Collect all files (Sequential I/O)
-    let entries: Vec<PathBuf> = WalkDir::new(wall_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file())
-        .map(|e| e.path().to_path_buf())
-        .collect();
+    Ok(thumb_dir)
+}
+
+/// Indexes a batch of files. Anything that isn't a readable image or video is skipped.
+/// Files whose key is in `known` keep that metadata instead of having it read again.
+fn index_files(paths: &[PathBuf], thumb_dir: &Path, known: &KnownMeta) -> Vec<Wallpaper> {
     // Process Images (Parallel CPU)
     // Rayon (.par_iter) distributes image resizing across all available CPU cores.
-    let wallpapers: Vec<Wallpaper> = entries.par_iter()
+    paths.par_iter()
         .filter_map(|path| {
-            // Skip video wallpapers (mp4, mkv) as image crate cannot handle them
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if ext_str == "mp4" || ext_str == "webm" || ext_str == "mkv" {
-                    return None;
-                }
-            }
-            let thumb = ensure_thumbnail(path, &thumb_dir)?;
+            let (key, thumb) = ensure_thumbnail(path, thumb_dir)?;
+            let meta = known.get(&key).cloned().unwrap_or_else(|| read_meta(path, &thumb));
             Some(Wallpaper {
                 name: path.file_stem()?.to_string_lossy().to_string(),
                 path: path.clone(),
                 thumb_path: thumb,
+                thumb_key: key,
+                video: video::is_video(path),
+                meta: Some(meta),
             })
         })
+        .collect()
+}
+
+/// All files under `dir` (Sequential I/O).
+fn files_under(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.path().to_path_buf())
+        .collect()
+}
+
+/// Writes the index for wp-select. Written to a temp file and renamed over the old one,
+/// so a reader never sees a half-written JSON.
+fn write_cache(index: &Index, cache_file: &Path) -> Result<()> {
+    let wallpapers: Vec<&Wallpaper> = index.values().collect();
+    let json = serde_json::to_string(&wallpapers)?;
+    let tmp_file = cache_file.with_extension("json.tmp");
+    fs::write(&tmp_file, json).context("Failed to write cache file")?;
+    fs::rename(&tmp_file, cache_file).context("Failed to replace cache file")?;
+    Ok(())
+}
+
+/// The core indexing logic, used at startup (and when the watcher lost track).
+/// 1. Walks the directory.
+/// 2. Generates thumbnails (or video posters) in parallel.
+/// 3. Skips anything unreadable.
+/// 4. Writes the master JSON index.
+/// 5. Sweeps the thumbnail folder.
+fn scan_and_update_cache(wall_dir: &Path, cache_file: &Path) -> Result<Index> {
+    let thumb_dir = thumb_dir()?;
+    println!("Scanning wallpapers in {:?}...", wall_dir);
+    // The previous cache saves re-reading metadata of files that haven't changed
+    let previous: Vec<Wallpaper> = fs::read_to_string(cache_file).ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    let known = known_meta(previous.iter());
+    let index: Index = index_files(&files_under(wall_dir), &thumb_dir, &known)
+        .into_iter()
+        .map(|w| (w.path.clone(), w))
         .collect();
     // Update Cache File
-    let json = serde_json::to_string(&wallpapers)?;
-    fs::write(cache_file, json).context("Failed to write cache file")?;
+    write_cache(&index, cache_file)?;
     //Garbage Collection
-    // Remove thumbnails for wallpapers that no longer exist.
-    let good_thumbs: HashSet<PathBuf> = wallpapers.into_iter()
-        .map(|w| w.thumb_path)
+    // Remove thumbnails whose key is no longer in the index: deleted wallpapers, old versions
+    // of edited ones, and thumbnails from the old name-based layout.
+    let good_keys: HashSet<&str> = index.values()
+        .map(|w| w.thumb_key.as_str())
         .collect();
     for entry in fs::read_dir(&thumb_dir)? {
         let entry = entry?;
         let thumb_path = entry.path();
-        if !good_thumbs.contains(&thumb_path) {
+        let key = thumb_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        if !good_keys.contains(key.as_str()) {
             println!("Garbage collecting old thumb: {:?}", thumb_path);
             let _ = fs::remove_file(thumb_path);
         }
     }
-    println!("Cache update. Found {} wallpapers.", good_thumbs.len());
-    Ok(())
+    println!("Cache update. Found {} wallpapers.", index.len());
+    Ok(index)
+}
+
+/// Deletes the thumbnail of an entry that just left the index, unless another entry still
+/// uses it: the key comes from the canonical path, so a symlink and its target share one.
+fn release_thumb(index: &Index, old: &Wallpaper) {
+    if !index.values().any(|w| w.thumb_key == old.thumb_key) {
+        let _ = fs::remove_file(&old.thumb_path);
+    }
+}
+
+/// Applies one batch of touched paths to the index. Returns true if anything changed.
+/// A path that is gone drops its entry (or, for a folder, everything under it); a path
+/// that exists is (re-)indexed, so adds, edits and both halves of a rename are covered.
+fn update_index(index: &mut Index, touched: &HashSet<PathBuf>, thumb_dir: &Path) -> bool {
+    let mut changed = false;
+    let mut to_index = Vec::new();
+    for path in touched {
+        if path.is_dir() {
+            to_index.extend(files_under(path)); // A folder moved or copied in
+        } else if path.is_file() {
+            to_index.push(path.clone());
+        } else {
+            let gone: Vec<PathBuf> = index.range(path.clone()..)
+                .take_while(|(p, _)| p.starts_with(path))
+                .map(|(p, _)| p.clone())
+                .collect();
+            for p in gone {
+                if let Some(old) = index.remove(&p) {
+                    release_thumb(index, &old);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Files that are unchanged keep their key; only new/edited ones cost a thumbnail.
+    let indexed = index_files(&to_index, thumb_dir, &known_meta(index.values()));
+    let indexed_paths: HashSet<&PathBuf> = indexed.iter().map(|w| &w.path).collect();
+    for path in &to_index {
+        // Present but not (or no longer) a readable image.
+        if !indexed_paths.contains(path)
+            && let Some(old) = index.remove(path)
+        {
+            release_thumb(index, &old);
+            changed = true;
+        }
+    }
+    for wallpaper in indexed {
+        let key = wallpaper.thumb_key.clone();
+        match index.insert(wallpaper.path.clone(), wallpaper) {
+            Some(old) if old.thumb_key == key => {} // Touched but not actually changed
+            Some(old) => {
+                release_thumb(index, &old); // Edited in place: the old version's thumb
+                changed = true;
+            }
+            None => changed = true,
+        }
+    }
+    changed
+}
+
+/// Pauses every mpvpaper player while on battery and resumes them on AC.
+/// Polled rather than event-driven: a few sysfs reads every few seconds cost nothing.
+fn watch_power() {
+    let mut was_on_battery = None;
+    loop {
+        let on_battery = video::on_battery();
+        if was_on_battery != Some(on_battery) {
+            for socket in video::ipc_sockets() {
+                video::set_paused(&socket, on_battery);
+            }
+            was_on_battery = Some(on_battery);
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+}
+
+/// Paths touched by one burst of events.
+#[derive(Default)]
+struct Batch {
+    touched: HashSet<PathBuf>,
+    rescan: bool, // The watcher dropped events; only a full scan can be trusted
+}
+
+impl Batch {
+    fn add(&mut self, res: notify::Result<notify::Event>) {
+        match res {
+            Ok(event) => {
+                if event.need_rescan() {
+                    self.rescan = true;
+                }
+                // FILTER: Ignore access events, metadata changes, or other noise.
+                // We only care if a file was created, written, renamed, or removed.
+                match event.kind {
+                    EventKind::Create(_)
+                    | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
+                    | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                    | EventKind::Remove(_) => self.touched.extend(event.paths),
+                    _ => {} // Ignore everything else (Access, Chmod, etc.)
+                }
+            }
+            Err(e) => eprintln!("Watch error {:?}", e),
+        }
+    }
+}
+
+/// Blocks for the next event, then keeps collecting until nothing arrived for `quiet`.
+/// Copying 200 wallpapers in becomes one batch instead of hundreds of rescans.
+fn next_batch(rx: &Receiver<notify::Result<notify::Event>>, quiet: Duration) -> Option<Batch> {
+    let mut batch = Batch::default();
+    batch.add(rx.recv().ok()?);
+    while let Ok(res) = rx.recv_timeout(quiet) {
+        batch.add(res);
+    }
+    Some(batch)
 }
+
 fn main() -> Result<()> {
     let global_config = load_config()?;
     let config = global_config.wallpaper_manager;
@@ -149,10 +432,16 @@ fn main() -> Result<()> {
     if !wall_dir.exists() {
         anyhow::bail!("Wallpaper directory does not exist: {:?}", wall_dir);
     }
-    //Initial scan on startup
-    if let Err(e) = scan_and_update_cache(&wall_dir, &cache_file) {
-        eprintln!("Initial scan failed: {}", e);
+    let thumb_dir = thumb_dir()?;
+    if config.pause_video_on_battery {
+        thread::spawn(watch_power);
     }
+    //Initial scan on startup
+    // None until a full scan succeeds: an incremental update of an empty index would save
+    // a near-empty cache over the good one, and wp-select would lose the whole library.
+    let mut index = scan_and_update_cache(&wall_dir, &cache_file)
+        .inspect_err(|e| eprintln!("Initial scan failed: {:#} (retrying on the next change)", e))
+        .ok();
     // Real-time Filesystem Watcher
     // Uses inotify (Linux) to trigger updates immediately when files are added/removed.
     let (tx, rx) = channel();
@@ -160,24 +449,24 @@ fn main() -> Result<()> {
     watcher.watch(&wall_dir, RecursiveMode::Recursive)?;
     println!("Daemon started. Watching {:?}...", wall_dir);
     // Event Loop
-    for res in rx {
-        match res {
-            Ok(event) => {
-                // FILTER: Ignore access events, metadata changes, or other noise.
-                // We only care if a file was created, modified (content), or removed.
-                use notify::EventKind;
-                match event.kind {
-                    EventKind::Create(_) | EventKind::Modify(notify::event::ModifyKind::Data(_)) | EventKind::Remove(_) => {
-                        println!("Relevant change detected ({:?}). Refreshing cache...", event.kind);
-                        // Debounce? (Optional optimization, but this filter usually fixes the loop)
-                        if let Err(e) = scan_and_update_cache(&wall_dir, &cache_file) {
-                            eprintln!("Error updating cache: {}", e);
-                        }
-                    },
-                    _ => {} // Ignore everything else (Access, Chmod, etc.)
-                }
-            },
-            Err(e) => eprintln!("Watch error {:?}", e),
+    let quiet = Duration::from_millis(config.reindex_debounce_ms);
+    while let Some(batch) = next_batch(&rx, quiet) {
+        let Some(current) = index.as_mut().filter(|_| !batch.rescan) else {
+            if batch.rescan {
+                println!("Watcher lost events. Rescanning...");
+            }
+            match scan_and_update_cache(&wall_dir, &cache_file) {
+                Ok(fresh) => index = Some(fresh),
+                Err(e) => eprintln!("Error updating cache: {:#}", e),
+            }
+            continue;
+        };
+        if batch.touched.is_empty() || !update_index(current, &batch.touched, &thumb_dir) {
+            continue;
+        }
+        println!("{} path(s) changed. Cache now has {} wallpapers.", batch.touched.len(), current.len());
+        if let Err(e) = write_cache(current, &cache_file) {
+            eprintln!("Error updating cache: {}", e);
         }
     }
     Ok(())
@@ -0,0 +1,285 @@
+//! Wallpaper Slideshow (wp-rotate)
+//!
+//! A background service that cycles wallpapers on every monitor on its own schedule.
+//! 1. Reads the index that `wp-daemon` maintains (no scanning of its own).
+//! 2. Picks the next wallpaper per monitor, in order or shuffled, optionally limited to a subfolder
+//!    or a tag.
+//! 3. Delegates the actual change to `wp-apply`, so every compositor backend just works.
+//! 4. Persists per-monitor state (per compositor, like `wallpaper_manager::state`), so the
+//!    schedule survives reboots and shuffle doesn't repeat what was shown recently.
+//!
+//! Configured under `[wallpaper_manager.rotation]`; exits right away unless `enabled = true`.
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use wallpaper_manager::library::Library;
+use wallpaper_manager::monitors::{get_compositor, get_monitor_list};
+use wallpaper_manager::state;
+
+fn expand_path(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
+    }
+    PathBuf::from(path)
+}
+
+#[derive(Deserialize, Debug)]
+struct WallpaperManagerConfig {
+    wallpaper_dir: String,
+    cache_file: String,
+    #[serde(default)]
+    rotation: RotationConfig,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct RotationConfig {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_history")]
+    history: usize,                     // Shuffle won't repeat any of the last N wallpapers
+    #[serde(flatten)]
+    defaults: Schedule,                 // Applies to every monitor...
+    #[serde(default)]
+    outputs: HashMap<String, Schedule>, // ...unless overridden here, per monitor
+}
+
+fn default_history() -> usize { 20 }
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Sequential,
+    #[default]
+    Shuffle,
+}
+
+/// One monitor's schedule. Every field is optional so per-output tables only list what differs.
+#[derive(Deserialize, Debug, Default, Clone)]
+struct Schedule {
+    enabled: Option<bool>,        // false = leave this monitor alone
+    interval_minutes: Option<u64>,
+    order: Option<Order>,
+    folder: Option<String>,       // Only wallpapers under this subfolder of wallpaper_dir
+    tag: Option<String>,          // Only wallpapers with this tag (set in wp-select)
+    include_videos: Option<bool>,
+}
+
+impl Schedule {
+    /// Fills the gaps in a per-output table from the defaults.
+    fn over(&self, defaults: &Schedule) -> Schedule {
+        Schedule {
+            enabled: self.enabled.or(defaults.enabled),
+            interval_minutes: self.interval_minutes.or(defaults.interval_minutes),
+            order: self.order.or(defaults.order),
+            folder: self.folder.clone().or_else(|| defaults.folder.clone()),
+            tag: self.tag.clone().or_else(|| defaults.tag.clone()),
+            include_videos: self.include_videos.or(defaults.include_videos),
+        }
+    }
+
+    fn interval_secs(&self) -> u64 {
+        self.interval_minutes.unwrap_or(30).max(1) * 60
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GlobalConfig {
+    wallpaper_manager: WallpaperManagerConfig,
+}
+
+fn load_config() -> Result<GlobalConfig> {
+    let config_path = dirs::home_dir()
+        .context("Cannot find home dir")?
+        .join(".config/rust-dotfiles/config.toml");
+
+    let config_str = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file from path: {}", config_path.display()))?;
+
+    let config: GlobalConfig = toml::from_str(&config_str)
+        .context("Failed to parse config.toml. Check for syntax errors.")?;
+
+    Ok(config)
+}
+
+/// The fields of wp-daemon's index that rotation needs.
+#[derive(Deserialize, Debug)]
+struct Wallpaper {
+    path: PathBuf,
+    #[serde(default)]
+    video: bool,
+}
+
+// --- Persisted State ---
+
+/// What was shown on one monitor, and when.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct OutputState {
+    last_change: u64,         // Unix seconds
+    last: Option<PathBuf>,    // Where sequential order continues from
+    recent: VecDeque<PathBuf>, // Newest first; shuffle avoids these
+}
+
+/// output -> state, for the running compositor.
+type State = HashMap<String, OutputState>;
+
+/// compositor -> output -> state: Sway, Hyprland and Niri each keep their own schedule.
+type StateFile = HashMap<String, State>;
+
+fn state_file() -> Result<PathBuf> {
+    Ok(dirs::cache_dir().context("Cannot find cache dir")?.join("wp-rotate-state.json"))
+}
+
+/// Saves this compositor's part, keeping the others' (a second session may be rotating too).
+fn save_state(path: &Path, compositor: &str, state: &State) -> Result<()> {
+    state::update_json(path, |file: &mut StateFile| {
+        file.insert(compositor.to_string(), state.clone());
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// --- Picking ---
+
+/// Small xorshift, seeded from the clock: plenty for picking a wallpaper, no `rand` needed.
+fn random_below(n: usize) -> usize {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    let mut x = nanos ^ ((std::process::id() as u64) << 32) ^ 0x9e3779b97f4a7c15;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x % n as u64) as usize
+}
+
+/// Wallpapers this schedule may show, sorted by path (the sequential order).
+fn candidates(cache_file: &Path, wall_dir: &Path, schedule: &Schedule) -> Result<Vec<PathBuf>> {
+    let json = fs::read_to_string(cache_file)
+        .context("Wallpaper cache missing! Please run 'wp-daemon' first.")?;
+    let wallpapers: Vec<Wallpaper> = serde_json::from_str(&json).context("Failed to parse wallpaper cache")?;
+    let folder = schedule.folder.as_deref().map(|f| wall_dir.join(f));
+    // Only a tag filter needs the library; a damaged one shouldn't stop untagged rotation.
+    let library = match schedule.tag {
+        Some(_) => Library::load(cache_file)?,
+        None => Library::default(),
+    };
+    let mut paths: Vec<PathBuf> = wallpapers.into_iter()
+        .filter(|w| schedule.include_videos.unwrap_or(false) || !w.video)
+        .filter(|w| folder.as_ref().is_none_or(|f| w.path.starts_with(f)))
+        .filter(|w| schedule.tag.as_ref().is_none_or(|t| library.has_tag(&w.path, t)))
+        .map(|w| w.path)
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn pick(paths: &[PathBuf], order: Order, state: &OutputState) -> PathBuf {
+    match order {
+        Order::Sequential => {
+            // The one after the last shown (by path), wrapping around. If the last one was
+            // deleted, this still continues from where it would have been.
+            let next = state.last.as_ref()
+                .map(|last| paths.partition_point(|p| p <= last))
+                .unwrap_or(0);
+            paths[next % paths.len()].clone()
+        }
+        Order::Shuffle => {
+            // Skip the recent ones, but always leave at least one to choose from.
+            let avoid = state.recent.len().min(paths.len() - 1);
+            let recent: Vec<&PathBuf> = state.recent.iter().take(avoid).collect();
+            let fresh: Vec<&PathBuf> = paths.iter().filter(|p| !recent.contains(p)).collect();
+            fresh[random_below(fresh.len())].clone()
+        }
+    }
+}
+
+/// Hands the chosen wallpaper to the sibling `wp-apply`, like wp-select does.
+fn apply(path: &Path, compositor: &str, output: &str) -> Result<()> {
+    let current_exe = env::current_exe()?;
+    let apply_path = current_exe.parent().context("Cannot locate wp-apply")?.join("wp-apply");
+    let status = Command::new(apply_path)
+        .arg(path)
+        .arg(compositor)
+        .arg(output)
+        .status()
+        .context("Failed to run 'wp-apply' command")?;
+    anyhow::ensure!(status.success(), "wp-apply failed for {}", output);
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let global_config = load_config()?;
+    let config = global_config.wallpaper_manager;
+    let rotation = &config.rotation;
+    if !rotation.enabled {
+        println!("Rotation is disabled ([wallpaper_manager.rotation] enabled = false).");
+        return Ok(());
+    }
+    let compositor = get_compositor();
+    if compositor == "unknown" {
+        anyhow::bail!("No supported compositor running.");
+    }
+    let wall_dir = expand_path(&config.wallpaper_dir);
+    let cache_file = expand_path(&config.cache_file);
+    let state_path = state_file()?;
+    let mut state = state::load_json::<StateFile>(&state_path).remove(&compositor).unwrap_or_default();
+    println!("Rotation started on {}.", compositor);
+
+    loop {
+        // Monitors are re-queried every pass, so hotplugged ones join the rotation.
+        let outputs = get_monitor_list(&compositor).unwrap_or_else(|e| {
+            eprintln!("Monitor query failed: {}", e);
+            Vec::new()
+        });
+        let now = unix_now();
+        let mut next_wake = now + 60;
+
+        for output in outputs {
+            let schedule = rotation.outputs.get(&output)
+                .map_or_else(|| rotation.defaults.clone(), |s| s.over(&rotation.defaults));
+            if schedule.enabled == Some(false) {
+                continue;
+            }
+            let output_state = state.entry(output.clone()).or_default();
+            let mut due = output_state.last_change + schedule.interval_secs();
+
+            if now >= due {
+                match candidates(&cache_file, &wall_dir, &schedule) {
+                    Ok(paths) if paths.is_empty() => eprintln!("{}: no wallpapers match this schedule", output),
+                    Ok(paths) => {
+                        let chosen = pick(&paths, schedule.order.unwrap_or_default(), output_state);
+                        println!("{}: {}", output, chosen.display());
+                        match apply(&chosen, &compositor, &output) {
+                            Ok(()) => {
+                                output_state.recent.retain(|p| p != &chosen);
+                                output_state.recent.push_front(chosen.clone());
+                                output_state.recent.truncate(rotation.history);
+                                output_state.last = Some(chosen);
+                            }
+                            Err(e) => eprintln!("{:#}", e),
+                        }
+                    }
+                    Err(e) => eprintln!("{:#}", e),
+                }
+                // Even on failure: retry at the next interval rather than every pass.
+                output_state.last_change = now;
+                due = now + schedule.interval_secs();
+                if let Err(e) = save_state(&state_path, &compositor, &state) {
+                    eprintln!("Failed to save rotation state: {}", e);
+                }
+            }
+            next_wake = next_wake.min(due);
+        }
+
+        thread::sleep(Duration::from_secs(next_wake.saturating_sub(unix_now()).max(1)));
+    }
+}
@@ -1,11 +1,19 @@
 //! Wallpaper Selector (wp-select)
 //!
 //! The User Interface for the wallpaper system.
-//! 1. Detects the current compositor environment (IPC).
+//! 1. Detects the current compositor environment (IPC, see `wallpaper_manager::monitors`).
 //! 2. Queries active monitors dynamically.
 //! 3. Reads the pre-generated cache (from wp-daemon) for instant startup.
 //! 4. Uses `rofi` as a GUI frontend to display thumbnails and filter results.
 //! 5. Delegates the final action to `wp-apply`.
+//!
+//! The list can be narrowed to what suits the chosen monitor and ordered differently, using
+//! the metadata wp-daemon records (`select_filter` / `select_sort`, or per run):
+//!   wp-select [--filter all|aspect|resolution] [--sort name|newest|color] [--tag <name>]
+//!
+//! Recently applied wallpapers come first, then favorites (see `wallpaper_manager::library`).
+//! In the menu, Ctrl+S toggles a favorite, Ctrl+T edits tags and typing `tag:<name>` + Enter
+//! shows only that tag.
 
 use std::fs;
 use std::env;
@@ -14,12 +22,16 @@ use std::path::{PathBuf, Path};
 use std::process::{Command, Stdio};
 use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use wallpaper_manager::monitors::{get_compositor, get_monitor_list, get_monitors};
+use wallpaper_manager::library::Library;
+use wallpaper_manager::palette::Rgb;
 
 fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(stripped);
-        }
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
     }
     PathBuf::from(path)
 }
@@ -34,8 +46,37 @@ struct WallpaperManagerConfig {
     cache_file: String,
     rofi_config_path: String,
     rofi_theme_override: String,
+    #[serde(default)]
+    select_filter: Filter,
+    #[serde(default)]
+    select_sort: Sort,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Filter {
+    #[default]
+    All,
+    Aspect,     // Same aspect ratio as the monitor (within ASPECT_TOLERANCE)
+    Resolution, // At least the monitor's resolution, so nothing gets upscaled
 }
 
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    #[default]
+    Name,
+    Newest,
+    Color, // Grouped by dominant hue, greys first
+}
+
+const ASPECT_TOLERANCE: f64 = 0.05;
+const RECENT_SHOWN: usize = 6; // One row of the wallpaper grid
+
+const ICON_RECENT: &str = "🕘";
+const ICON_FAVORITE: &str = "★";
+const MENU_HINT: &str = "<b>Enter:</b> Apply  |  <b>Ctrl+S:</b> Favorite  |  <b>Ctrl+T:</b> Tags  |  <b>tag:name</b> Filter";
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     wallpaper_manager: WallpaperManagerConfig,
@@ -54,91 +95,133 @@ fn load_config() -> Result<GlobalConfig> {
     
     Ok(config)
 }
-// --- IPC Structures ---
-// These match the JSON output of hyprctl and swaymsg
-#[derive(Deserialize, Debug)]
-struct HyprMonitor {
-    name: String,
-}
-#[derive(Deserialize, Debug)]
-struct SwayMonitor {
-    name: String,
-    active: bool,
-}
 #[derive(Deserialize, Debug, Clone)]
 struct Wallpaper {
     name: String,
     path: PathBuf,
     thumb_path: PathBuf,
+    #[serde(default)]
+    meta: Option<Meta>, // Missing in caches from older wp-daemon versions
 }
-/// Heuristic to determine the running Window Manager.
-/// Checks IPC sockets and Environment variables.
-fn get_compositor() -> String {
-    if env::var("NIRI_SOCKET").is_ok() { return "niri".to_string(); }
-    if env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() { return "hyprland".to_string(); }
-    if env::var("SWAYSOCK").is_ok() { return "sway".to_string(); }
-    
-    if let Ok(desktop) = env::var("XDG_CURRENT_DESKTOP") {
-        let d = desktop.to_lowercase();
-        if d.contains("niri") { return "niri".to_string(); }
-        if d.contains("hypr") { return "hyprland".to_string(); }
-        if d.contains("sway") { return "sway".to_string(); }
-    }
-    "unknown".to_string()
-}
-/// Queries the compositor for a list of connected screens.
-/// This allows per-monitor wallpaper setting.
-fn get_monitor_list(compositor: &str) -> Result<Vec<String>> {
-    let output;
-    match compositor {
-        "hyprland" => {
-            // Parse `hyprctl monitors -j`
-            output = Command::new("hyprctl").arg("-j").arg("monitors").output()?;
-            if !output.status.success() {
-                anyhow::bail!("hyprctl command failed");
+
+/// The parts of wp-daemon's metadata used here.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct Meta {
+    width: u32,
+    height: u32,
+    modified: u64,
+    dominant_color: String,
+}
+
+impl Wallpaper {
+    /// Does it suit a monitor of `width`x`height` pixels? Unknown sizes always pass.
+    fn fits(&self, filter: Filter, (width, height): (u32, u32)) -> bool {
+        let Some(meta) = self.meta.as_ref().filter(|m| m.width > 0 && m.height > 0) else { return true };
+        match filter {
+            Filter::All => true,
+            Filter::Aspect => {
+                let ratio = (meta.width as f64 / meta.height as f64) / (width as f64 / height as f64);
+                (ratio - 1.0).abs() <= ASPECT_TOLERANCE
             }
-            let monitors: Vec<HyprMonitor> = serde_json::from_slice(&output.stdout)
-                .context("Failed to parse hyprctl JSON")?;
-            Ok(monitors.into_iter().map(|m| m.name).collect())
+            Filter::Resolution => meta.width >= width && meta.height >= height,
         }
-        "sway" => {
-            // Parse `swaymsg -t get_outputs`
-            output = Command::new("swaymsg").arg("-t").arg("get_outputs").output()?;
-            if !output.status.success() {
-                anyhow::bail!("swaymsg command failed");
-            }
-            let monitors: Vec<SwayMonitor> = serde_json::from_slice(&output.stdout)
-                .context("Failed to parse swaymsg JSON")?;
-            Ok(monitors
-                .into_iter()
-                .filter(|m| m.active)
-                .map(|m| m.name)
-                .collect())
+    }
+
+    fn color(&self) -> Option<Rgb> {
+        Rgb::from_hex(&self.meta.as_ref()?.dominant_color)
+    }
+
+    /// Sort key for `Sort::Color`: greys (and unknown) first by brightness, then 12 hue groups.
+    fn color_group(&self) -> (usize, i64) {
+        match self.color() {
+            Some(c) if c.saturation() >= 0.15 => (1 + (c.hue() / 30.0) as usize % 12, -(c.luminance() * 1000.0) as i64),
+            Some(c) => (0, -(c.luminance() * 1000.0) as i64),
+            None => (0, 0),
         }
-        "niri" => {
-            // Niri uses swww-daemon as its "state of truth" for monitors context
-            output = Command::new("swww")
-                .arg("query")
-                .arg("--namespace")
-                .arg("niri")
-                .output()?;
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                anyhow::bail!("swww query failed: {}", stderr);
-            }
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let monitors: Vec<String> = stdout
-                .lines()
-                .filter_map(|line| {
-                    let parts: Vec<&str> = line.split(':').collect();
-                    parts.get(1).map(|s| s.trim().to_string())
-                })
-                .collect();
-            Ok(monitors)
+    }
+
+    /// The rofi row: markers, name and tags.
+    fn label(&self, sort: Sort, library: &Library, recent: bool) -> String {
+        let mut label = String::new();
+        if recent {
+            label.push_str(ICON_RECENT);
+            label.push(' ');
+        }
+        if library.is_favorite(&self.path) {
+            label.push_str(ICON_FAVORITE);
+            label.push(' ');
+        }
+        if sort == Sort::Color
+            && let Some(color) = self.color()
+        {
+            label.push_str(&format!("<span foreground=\"{}\">\u{25CF}</span> ", color));
+        }
+        label.push_str(&escape_markup(&self.name));
+        let tags: Vec<String> = library.tags_of(&self.path).map(|t| format!("#{}", escape_markup(t))).collect();
+        if !tags.is_empty() {
+            label.push_str(&format!(" <small>{}</small>", tags.join(" ")));
         }
-        _ => Err(anyhow!("Unknown compositor for monitor detection")),
+        label
+    }
+}
+
+/// Rows are Pango markup (`-markup-rows`), so names like "Rock & Roll" need escaping.
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Menu order: the recently applied (newest first), then favorites, then everything else
+/// (each keeps the `--sort` order). Returns indices into `wallpapers`, flagged if recent.
+fn menu_order(wallpapers: &[&Wallpaper], library: &Library) -> Vec<(usize, bool)> {
+    let recent: Vec<usize> = library.recent.iter()
+        .filter_map(|r| wallpapers.iter().position(|w| w.path == r.path))
+        .take(RECENT_SHOWN)
+        .collect();
+    let (favorites, rest): (Vec<usize>, Vec<usize>) = (0..wallpapers.len())
+        .filter(|i| !recent.contains(i))
+        .partition(|&i| library.is_favorite(&wallpapers[i].path));
+    recent.into_iter().map(|i| (i, true))
+        .chain(favorites.into_iter().chain(rest).map(|i| (i, false)))
+        .collect()
+}
+
+/// Parses a `--filter`/`--sort` value with the same names as the config.
+fn parse_arg<T: DeserializeOwned>(flag: &str, value: Option<&String>) -> Result<T> {
+    let value = value.with_context(|| format!("{} needs a value", flag))?;
+    T::deserialize(value.as_str().into_deserializer())
+        .map_err(|e: serde::de::value::Error| anyhow!("Invalid {} '{}': {}", flag, value, e))
+}
+/// The wallpaper menu: like `ask_rofi`, plus the favorite/tag keys.
+/// Returns the exit code (0 = Enter, 1 = Esc, 10 = Ctrl+S, 11 = Ctrl+T), the index of the
+/// selected row (None for typed text that matches no row) and the typed text.
+/// Rows are told apart by index: the same file name can appear in several folders.
+fn show_menu(prompt: &str, items: Vec<String>, conf: &Path, theme: &str, mesg: &str) -> Result<(i32, Option<usize>, String)> {
+    let mut child = Command::new("rofi")
+        .args(["-dmenu", "-i", "-p", prompt, "-markup-rows"])
+        .args(["-format", "i f"]) // "<row index, -1 if none> <filter text>"
+        .arg("-config").arg(conf)
+        .arg("-theme-str").arg(theme)
+        .arg("-mesg").arg(mesg)
+        // Define custom return codes for keybinds
+        .arg("-kb-custom-1")
+        .arg("Control+s") // Favorite
+        .arg("-kb-custom-2")
+        .arg("Control+t") // Tags
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn rofi")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(items.join("\n").as_bytes())?;
     }
+    let output = child.wait_with_output()?;
+    let code = output.status.code().unwrap_or(1); // Default to 1 (Cancel) on failure
+    let stdout = String::from_utf8(output.stdout)?;
+    let (index, filter) = stdout.trim_end_matches('\n').split_once(' ').unwrap_or((stdout.trim(), ""));
+    Ok((code, index.parse().ok(), filter.trim().to_string()))
 }
+
 /// Wraps the `rofi` command line interface.
 /// Pipes the list of items into rofi's STDIN and captures the selection from STDOUT.
 fn ask_rofi(prompt: &str, items: Vec<String>, config: Option<(&Path, &str)>) -> Result<String> {
@@ -164,6 +247,20 @@ fn ask_rofi(prompt: &str, items: Vec<String>, config: Option<(&Path, &str)>) ->
 fn main() -> Result<()> {
     let global_config = load_config()?;
     let config = global_config.wallpaper_manager;
+    let mut filter = config.select_filter;
+    let mut sort = config.select_sort;
+    let mut tag: Option<String> = None;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--filter" => filter = parse_arg("--filter", args.get(i + 1))?,
+            "--sort" => sort = parse_arg("--sort", args.get(i + 1))?,
+            "--tag" => tag = Some(args.get(i + 1).context("--tag needs a value")?.to_lowercase()),
+            other => anyhow::bail!("Unknown argument '{}'", other),
+        }
+        i += 2;
+    }
     // Environment Discovery
     let compositor = get_compositor();
     if compositor == "unknown" {
@@ -187,32 +284,151 @@ fn main() -> Result<()> {
 
     let json_str = fs::read_to_string(&cache_file)?;
     let mut wallpapers: Vec<Wallpaper> = serde_json::from_str(&json_str)?;
-    wallpapers.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    // Build Rofi Menu with Icons
-    // Rofi supports icons via the `\0icon\x1f` delimiter syntax.
-    let rofi_items: Vec<String> = wallpapers.iter().map(|wp| {
-        format!("{}\0icon\x1f{}", wp.name, wp.thumb_path.to_string_lossy())
-    }).collect();
+
+    // Filtering by the chosen monitor's size (as drawn: rotated screens swap it)
+    let mut prompt = "Select Wallpaper".to_string();
+    if filter != Filter::All {
+        let monitor = get_monitors(&compositor).ok()
+            .and_then(|monitors| monitors.into_iter().find(|m| m.name == chosen_monitor))
+            .filter(|m| m.width > 0 && m.height > 0);
+        match monitor {
+            Some(monitor) => {
+                let size = monitor.pixels();
+                let matching: Vec<Wallpaper> = wallpapers.iter().filter(|w| w.fits(filter, size)).cloned().collect();
+                if matching.is_empty() {
+                    eprintln!("Nothing matches {}x{}; showing everything.", size.0, size.1);
+                } else {
+                    wallpapers = matching;
+                    prompt = format!("Select Wallpaper ({}x{})", size.0, size.1);
+                }
+            }
+            None => eprintln!("Resolution of {} unknown; showing everything.", chosen_monitor),
+        }
+    }
+
+    match sort {
+        Sort::Name => wallpapers.sort_by_key(|w| w.name.to_lowercase()),
+        Sort::Newest => wallpapers.sort_by_key(|w| std::cmp::Reverse(w.meta.as_ref().map_or(0, |m| m.modified))),
+        Sort::Color => wallpapers.sort_by_key(|w| (w.color_group(), w.name.to_lowercase())),
+    }
     let rofi_conf_path = expand_path(&config.rofi_config_path);
-    // User Interaction (Wallpaper Selection)
-    let selection_name = ask_rofi(
-        "Select Wallpaper",
-        rofi_items,
-        Some((&rofi_conf_path, &config.rofi_theme_override))
-    )?;
-    // Execution
-    // Determine the absolute path of the sibling binary `wp-apply` and execute it.
-    let selected_wp = wallpapers.into_iter().find(|w| w.name == selection_name)
-        .ok_or_else(|| anyhow!("Selected wallpaper not found in cache"))?;
     let current_exe = env::current_exe()?;
     let apply_path = current_exe.parent().unwrap().join("wp-apply");
 
-    Command::new(apply_path)
-        .arg(selected_wp.path)
-        .arg(&compositor)
-        .arg(&chosen_monitor)
-        .spawn()
-        .context("Failed to run 'wp-apply' command")?;
+    // Menu Loop
+    // Favorite/tag actions re-open the menu with the change shown, like clip-manager's delete.
+    loop {
+        // A damaged library is shown as empty and every edit is refused (see `Library::update`),
+        // so one bad hand edit can't turn into an empty file on the next Ctrl+S.
+        let (library, library_error) = match Library::load(&cache_file) {
+            Ok(library) => (library, None),
+            Err(e) => (Library::default(), Some(format!("{:#}", e))),
+        };
+        let shown: Vec<&Wallpaper> = wallpapers.iter()
+            .filter(|w| tag.as_ref().is_none_or(|t| library.has_tag(&w.path, t)))
+            .collect();
+        let order = menu_order(&shown, &library);
+        // Build Rofi Menu with Icons
+        // Rofi supports icons via the `\0icon\x1f` delimiter syntax.
+        let rofi_items: Vec<String> = order.iter().map(|&(i, recent)| {
+            format!("{}\0icon\x1f{}", shown[i].label(sort, &library, recent), shown[i].thumb_path.to_string_lossy())
+        }).collect();
+        let mesg = match (&library_error, &tag) {
+            (Some(e), _) => format!("<b>Favorites and tags unavailable:</b> {}", escape_markup(e)),
+            (None, Some(t)) => format!("Tag <b>#{}</b>  |  <b>Esc:</b> All wallpapers", escape_markup(t)),
+            (None, None) => MENU_HINT.to_string(),
+        };
+        // User Interaction (Wallpaper Selection)
+        let (code, row, typed) = show_menu(&prompt, rofi_items, &rofi_conf_path, &config.rofi_theme_override, &mesg)?;
+        let picked = row.and_then(|r| order.get(r)).map(|&(i, _)| shown[i]);
+
+        match (code, picked) {
+            (0, Some(selected_wp)) => {
+                // Execution
+                // Hand the selection to the sibling binary `wp-apply`.
+                Command::new(&apply_path)
+                    .arg(&selected_wp.path)
+                    .arg(&compositor)
+                    .arg(&chosen_monitor)
+                    .spawn()
+                    .context("Failed to run 'wp-apply' command")?;
+                return Ok(()); // wp-apply records it in the history
+            }
+            (0, None) => {
+                // Typed text that matches no wallpaper: a filter
+                match typed.strip_prefix("tag:").map(str::trim) {
+                    Some("") => tag = None,
+                    Some(t) => tag = Some(t.to_lowercase()),
+                    None if typed.is_empty() => return Ok(()),
+                    None => anyhow::bail!("Selected wallpaper not found in cache"),
+                }
+            }
+            (1, _) if tag.is_some() => tag = None, // Esc leaves the tag view first
+            (1, _) => return Ok(()),
+            (10, Some(wp)) => {
+                if let Err(e) = Library::update(&cache_file, |l| l.toggle_favorite(&wp.path)) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            (11, Some(wp)) => {
+                let current: Vec<&String> = library.tags_of(&wp.path).collect();
+                let prompt = format!("Tags for {} (now: {}; -tag removes)", wp.name, current.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(" "));
+                let suggestions: Vec<String> = library.all_tags().into_iter().cloned().collect();
+                // Esc here just goes back to the menu
+                if let Ok(input) = ask_rofi(&prompt, suggestions, None)
+                    && let Err(e) = Library::update(&cache_file, |l| l.edit_tags(&wp.path, &input))
+                {
+                    eprintln!("{:#}", e);
+                }
+            }
+            (10 | 11, None) => {} // Nothing highlighted
+            (code, _) => anyhow::bail!("Rofi failed with exit code: {}", code),
+        }
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallpaper(name: &str) -> Wallpaper {
+        Wallpaper { name: name.to_string(), path: PathBuf::from(format!("/w/{}", name)), thumb_path: PathBuf::new(), meta: None }
+    }
+
+    fn names(order: &[(usize, bool)], wallpapers: &[&Wallpaper]) -> Vec<(String, bool)> {
+        order.iter().map(|&(i, recent)| (wallpapers[i].name.clone(), recent)).collect()
+    }
+
+    #[test]
+    fn menu_order_puts_recent_then_favorites_first() {
+        let all: Vec<Wallpaper> = ["a", "b", "c", "d", "e"].into_iter().map(wallpaper).collect();
+        let shown: Vec<&Wallpaper> = all.iter().collect();
+        let mut library = Library::default();
+        library.toggle_favorite(Path::new("/w/d"));
+        library.toggle_favorite(Path::new("/w/b"));
+        library.touch(Path::new("/w/e"));
+        library.touch(Path::new("/w/b")); // Recent and favorite: listed once, as recent
+        library.touch(Path::new("/w/gone")); // Not in the list (filtered out or deleted)
+
+        let order = menu_order(&shown, &library);
+        assert_eq!(names(&order, &shown), [
+            ("b".to_string(), true), ("e".to_string(), true),
+            ("d".to_string(), false),
+            ("a".to_string(), false), ("c".to_string(), false),
+        ]);
+    }
+
+    #[test]
+    fn menu_order_limits_the_recent_rows() {
+        let all: Vec<Wallpaper> = (0..RECENT_SHOWN + 3).map(|i| wallpaper(&i.to_string())).collect();
+        let shown: Vec<&Wallpaper> = all.iter().collect();
+        let mut library = Library::default();
+        for w in &all {
+            library.touch(&w.path);
+        }
+        let order = menu_order(&shown, &library);
+        assert_eq!(order.len(), all.len());
+        assert_eq!(order.iter().filter(|&&(_, recent)| recent).count(), RECENT_SHOWN);
+        assert_eq!(order[0].0, all.len() - 1); // Newest first
+    }
 }
@@ -0,0 +1,8 @@
+//! Shared code for the `wp-daemon`, `wp-select`, `wp-apply` and `wp-rotate` binaries.
+
+pub mod library;
+pub mod monitors;
+pub mod palette;
+pub mod state;
+pub mod theme;
+pub mod video;
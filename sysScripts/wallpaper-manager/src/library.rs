@@ -0,0 +1,197 @@
+//! Favorites, Tags & History
+//!
+//! What the user says about their wallpapers, as opposed to what wp-daemon can read from
+//! the files. Kept in a sidecar next to the index (`wallpapers.json` ->
+//! `wallpapers.library.json`), which wp-daemon never rewrites. Entries are keyed by path.
+//! Favorites and tags are edited in wp-select; the history is recorded by wp-apply.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+const HISTORY_LEN: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Library {
+    #[serde(default)]
+    pub favorites: BTreeSet<PathBuf>,
+    #[serde(default)]
+    pub tags: BTreeMap<PathBuf, BTreeSet<String>>,
+    #[serde(default)]
+    pub recent: Vec<Recent>, // Newest first
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recent {
+    pub path: PathBuf,
+    pub applied: u64, // Unix seconds
+}
+
+/// The sidecar for an index file.
+pub fn library_file(cache_file: &Path) -> PathBuf {
+    cache_file.with_extension("library.json")
+}
+
+impl Library {
+    /// The library beside `cache_file`; empty if there is none yet. A file that exists but
+    /// can't be read or parsed is an error, never an empty library: saving that would
+    /// overwrite every favorite and tag.
+    pub fn load(cache_file: &Path) -> Result<Library> {
+        let path = library_file(cache_file);
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Library::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&json)
+            .with_context(|| format!("{} is damaged; fix or remove it to edit favorites and tags", path.display()))
+    }
+
+    /// Loads, changes and saves the library under an exclusive lock, so wp-apply recording
+    /// history and wp-select editing tags can't overwrite each other. Refuses (without
+    /// touching the file) if the current one can't be loaded.
+    pub fn update<R>(cache_file: &Path, change: impl FnOnce(&mut Library) -> R) -> Result<R> {
+        let path = library_file(cache_file);
+        // Locked through a sibling: the rename below replaces the library file's inode.
+        let lock = File::create(path.with_extension("lock"))?;
+        lock.lock().context("Failed to lock the wallpaper library")?;
+
+        let mut library = Library::load(cache_file)?;
+        let result = change(&mut library);
+        // Temp file + rename: favorites and tags are hand-made, a torn write would lose them.
+        let tmp = path.with_extension(format!("json.{}.tmp", process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(&library)?)?;
+        fs::rename(&tmp, &path).context("Failed to save the wallpaper library")?;
+        Ok(result)
+    }
+
+    pub fn is_favorite(&self, path: &Path) -> bool {
+        self.favorites.contains(path)
+    }
+
+    /// Returns whether it is a favorite now.
+    pub fn toggle_favorite(&mut self, path: &Path) -> bool {
+        if self.favorites.remove(path) {
+            false
+        } else {
+            self.favorites.insert(path.to_path_buf());
+            true
+        }
+    }
+
+    pub fn tags_of(&self, path: &Path) -> impl Iterator<Item = &String> {
+        self.tags.get(path).into_iter().flatten()
+    }
+
+    pub fn has_tag(&self, path: &Path, tag: &str) -> bool {
+        self.tags.get(path).is_some_and(|tags| tags.contains(&tag.to_lowercase()))
+    }
+
+    /// Every tag in use, for suggestions.
+    pub fn all_tags(&self) -> BTreeSet<&String> {
+        self.tags.values().flatten().collect()
+    }
+
+    /// Applies "nature, dark -old": plain words are added, "-word" removes. Tags are lowercase
+    /// and may not contain spaces or commas (they're separators).
+    pub fn edit_tags(&mut self, path: &Path, input: &str) {
+        let tags = self.tags.entry(path.to_path_buf()).or_default();
+        for word in input.split([',', ' ']).map(str::trim).filter(|w| !w.is_empty()) {
+            let word = word.trim_start_matches('#').to_lowercase();
+            match word.strip_prefix('-') {
+                Some(removed) => { tags.remove(removed); }
+                None => { tags.insert(word); }
+            }
+        }
+        if tags.is_empty() {
+            self.tags.remove(path);
+        }
+    }
+
+    /// Records that `path` was just applied.
+    pub fn touch(&mut self, path: &Path) {
+        let applied = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        self.recent.retain(|r| r.path != path);
+        self.recent.insert(0, Recent { path: path.to_path_buf(), applied });
+        self.recent.truncate(HISTORY_LEN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(library: &Library, path: &str) -> Vec<String> {
+        library.tags_of(Path::new(path)).cloned().collect()
+    }
+
+    #[test]
+    fn edit_tags_adds_removes_and_normalises() {
+        let mut library = Library::default();
+        library.edit_tags(Path::new("/w/a.jpg"), "Nature, #dark  sky");
+        assert_eq!(tags(&library, "/w/a.jpg"), ["dark", "nature", "sky"]);
+        library.edit_tags(Path::new("/w/a.jpg"), "-sky,-missing city");
+        assert_eq!(tags(&library, "/w/a.jpg"), ["city", "dark", "nature"]);
+    }
+
+    #[test]
+    fn edit_tags_drops_the_entry_once_empty() {
+        let mut library = Library::default();
+        library.edit_tags(Path::new("/w/a.jpg"), "nature");
+        library.edit_tags(Path::new("/w/a.jpg"), "-nature");
+        assert!(library.tags.is_empty());
+        library.edit_tags(Path::new("/w/b.jpg"), " , ");
+        assert!(library.tags.is_empty());
+    }
+
+    #[test]
+    fn has_tag_ignores_case() {
+        let mut library = Library::default();
+        library.edit_tags(Path::new("/w/a.jpg"), "nature");
+        assert!(library.has_tag(Path::new("/w/a.jpg"), "Nature"));
+        assert!(!library.has_tag(Path::new("/w/a.jpg"), "dark"));
+        assert!(!library.has_tag(Path::new("/w/b.jpg"), "nature"));
+    }
+
+    #[test]
+    fn touch_moves_to_the_front_without_duplicates() {
+        let mut library = Library::default();
+        for name in ["/w/a.jpg", "/w/b.jpg", "/w/a.jpg"] {
+            library.touch(Path::new(name));
+        }
+        let order: Vec<&Path> = library.recent.iter().map(|r| r.path.as_path()).collect();
+        assert_eq!(order, [Path::new("/w/a.jpg"), Path::new("/w/b.jpg")]);
+    }
+
+    #[test]
+    fn touch_keeps_the_history_bounded() {
+        let mut library = Library::default();
+        for i in 0..HISTORY_LEN + 10 {
+            library.touch(&PathBuf::from(format!("/w/{}.jpg", i)));
+        }
+        assert_eq!(library.recent.len(), HISTORY_LEN);
+        assert_eq!(library.recent[0].path, PathBuf::from(format!("/w/{}.jpg", HISTORY_LEN + 9)));
+    }
+
+    #[test]
+    fn a_damaged_library_is_never_overwritten() {
+        let dir = std::env::temp_dir().join(format!("wp-library-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_file = dir.join("wallpapers.json");
+        assert!(Library::load(&cache_file).unwrap().favorites.is_empty()); // None yet: empty
+
+        Library::update(&cache_file, |l| l.toggle_favorite(Path::new("/w/a.jpg"))).unwrap();
+        assert!(Library::load(&cache_file).unwrap().is_favorite(Path::new("/w/a.jpg")));
+
+        fs::write(library_file(&cache_file), "{ \"favorites\": [").unwrap();
+        assert!(Library::load(&cache_file).is_err());
+        assert!(Library::update(&cache_file, |l| l.touch(Path::new("/w/b.jpg"))).is_err());
+        assert_eq!(fs::read_to_string(library_file(&cache_file)).unwrap(), "{ \"favorites\": [");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! Compositor & Monitor Discovery
+//!
+//! Shared by `wp-select` (which monitor to apply to, and its resolution for filtering) and
+//! `wp-rotate` (which monitors to cycle).
+
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+// --- IPC Structures ---
+// These match the JSON output of hyprctl and swaymsg
+#[derive(Deserialize, Debug)]
+struct HyprMonitor {
+    name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default)]
+    transform: u8, // 0-7; odd values are rotated by 90 or 270 degrees
+}
+#[derive(Deserialize, Debug)]
+struct SwayMonitor {
+    name: String,
+    active: bool,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default)]
+    transform: Option<String>, // "normal", "90", "flipped-270"...
+    current_mode: Option<SwayMode>,
+}
+#[derive(Deserialize, Debug)]
+struct SwayMode {
+    width: u32,
+    height: u32,
+}
+// `niri msg --json outputs`: a map of connector name -> output
+#[derive(Deserialize, Debug)]
+struct NiriOutput {
+    modes: Vec<SwayMode>,
+    current_mode: Option<usize>,
+    logical: Option<NiriLogical>,
+}
+#[derive(Deserialize, Debug)]
+struct NiriLogical {
+    scale: f64,
+    transform: String, // "Normal", "90", "Flipped270"...
+}
+
+fn default_scale() -> f64 { 1.0 }
+
+/// One connected screen, in physical pixels.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    pub transform: String, // As the compositor reports it
+    pub rotated: bool,     // Turned by 90/270 degrees: width and height swap on screen
+}
+
+impl Monitor {
+    /// The size a wallpaper is drawn at: the mode, turned the way the screen is.
+    pub fn pixels(&self) -> (u32, u32) {
+        if self.rotated { (self.height, self.width) } else { (self.width, self.height) }
+    }
+}
+
+fn is_rotated(transform: &str) -> bool {
+    transform.contains("90") || transform.contains("270")
+}
+
+/// Heuristic to determine the running Window Manager.
+/// Checks IPC sockets and Environment variables.
+pub fn get_compositor() -> String {
+    if env::var("NIRI_SOCKET").is_ok() { return "niri".to_string(); }
+    if env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() { return "hyprland".to_string(); }
+    if env::var("SWAYSOCK").is_ok() { return "sway".to_string(); }
+    
+    if let Ok(desktop) = env::var("XDG_CURRENT_DESKTOP") {
+        let d = desktop.to_lowercase();
+        if d.contains("niri") { return "niri".to_string(); }
+        if d.contains("hypr") { return "hyprland".to_string(); }
+        if d.contains("sway") { return "sway".to_string(); }
+    }
+    "unknown".to_string()
+}
+/// Queries the compositor for a list of connected screens.
+/// This allows per-monitor wallpaper setting.
+pub fn get_monitor_list(compositor: &str) -> Result<Vec<String>> {
+    let output;
+    match compositor {
+        "hyprland" => {
+            // Parse `hyprctl monitors -j`
+            output = Command::new("hyprctl").arg("-j").arg("monitors").output()?;
+            if !output.status.success() {
+                anyhow::bail!("hyprctl command failed");
+            }
+            let monitors: Vec<HyprMonitor> = serde_json::from_slice(&output.stdout)
+                .context("Failed to parse hyprctl JSON")?;
+            Ok(monitors.into_iter().map(|m| m.name).collect())
+        }
+        "sway" => {
+            // Parse `swaymsg -t get_outputs`
+            output = Command::new("swaymsg").arg("-t").arg("get_outputs").output()?;
+            if !output.status.success() {
+                anyhow::bail!("swaymsg command failed");
+            }
+            let monitors: Vec<SwayMonitor> = serde_json::from_slice(&output.stdout)
+                .context("Failed to parse swaymsg JSON")?;
+            Ok(monitors
+                .into_iter()
+                .filter(|m| m.active)
+                .map(|m| m.name)
+                .collect())
+        }
+        "niri" => {
+            // Niri uses swww-daemon as its "state of truth" for monitors context
+            output = Command::new("swww")
+                .arg("query")
+                .arg("--namespace")
+                .arg("niri")
+                .output()?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                anyhow::bail!("swww query failed: {}", stderr);
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let monitors: Vec<String> = stdout
+                .lines()
+                .filter_map(|line| {
+                    let parts: Vec<&str> = line.split(':').collect();
+                    parts.get(1).map(|s| s.trim().to_string())
+                })
+                .collect();
+            Ok(monitors)
+        }
+        _ => Err(anyhow!("Unknown compositor for monitor detection")),
+    }
+}
+
+/// Like `get_monitor_list`, with each monitor's resolution, scale and transform.
+pub fn get_monitors(compositor: &str) -> Result<Vec<Monitor>> {
+    match compositor {
+        "hyprland" => {
+            let output = Command::new("hyprctl").arg("-j").arg("monitors").output()?;
+            anyhow::ensure!(output.status.success(), "hyprctl command failed");
+            let monitors: Vec<HyprMonitor> = serde_json::from_slice(&output.stdout)
+                .context("Failed to parse hyprctl JSON")?;
+            Ok(monitors.into_iter().map(|m| Monitor {
+                name: m.name,
+                width: m.width,
+                height: m.height,
+                scale: m.scale,
+                transform: m.transform.to_string(),
+                rotated: m.transform % 2 == 1,
+            }).collect())
+        }
+        "sway" => {
+            let output = Command::new("swaymsg").arg("-t").arg("get_outputs").output()?;
+            anyhow::ensure!(output.status.success(), "swaymsg command failed");
+            let monitors: Vec<SwayMonitor> = serde_json::from_slice(&output.stdout)
+                .context("Failed to parse swaymsg JSON")?;
+            Ok(monitors.into_iter().filter(|m| m.active).map(|m| {
+                let transform = m.transform.unwrap_or_else(|| "normal".to_string());
+                let (width, height) = m.current_mode.map_or((0, 0), |mode| (mode.width, mode.height));
+                Monitor { name: m.name, width, height, scale: m.scale, rotated: is_rotated(&transform), transform }
+            }).collect())
+        }
+        "niri" => {
+            // swww only knows names and sizes; niri itself has the full picture
+            let output = Command::new("niri").arg("msg").arg("--json").arg("outputs").output()?;
+            anyhow::ensure!(output.status.success(), "niri msg command failed");
+            let outputs: HashMap<String, NiriOutput> = serde_json::from_slice(&output.stdout)
+                .context("Failed to parse niri JSON")?;
+            Ok(outputs.into_iter()
+                .filter_map(|(name, o)| {
+                    let mode = o.modes.get(o.current_mode?)?; // No current mode: disabled
+                    let (scale, transform) = o.logical.map_or((1.0, "Normal".to_string()), |l| (l.scale, l.transform));
+                    Some(Monitor { name, width: mode.width, height: mode.height, scale, rotated: is_rotated(&transform), transform })
+                })
+                .collect())
+        }
+        _ => Err(anyhow!("Unknown compositor for monitor detection")),
+    }
+}
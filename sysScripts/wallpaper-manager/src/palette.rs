@@ -0,0 +1,321 @@
+//! Color Palette Extraction
+//!
+//! Builds a 16-color terminal-style palette (plus background, foreground and accent) from a
+//! wallpaper, so themes can follow it without an external Pywal-style tool.
+//! 1. Downscale the image: a palette doesn't need every pixel.
+//! 2. Median cut: split the pixels into boxes along their widest channel until there are 16.
+//! 3. Pick roles from the box averages: darkest = background, lightest = foreground,
+//!    most vivid (weighted by how much of the image it covers) = accent.
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use image::imageops::FilterType;
+use crate::video;
+
+const SAMPLE_SIZE: u32 = 128; // Longest side of the image the pixels are sampled from
+const SWATCHES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// Relative luminance, 0.0 (black) to 1.0 (white).
+    pub fn luminance(self) -> f64 {
+        (0.2126 * self.0 as f64 + 0.7152 * self.1 as f64 + 0.0722 * self.2 as f64) / 255.0
+    }
+
+    /// HSV saturation, 0.0 (grey) to 1.0.
+    pub fn saturation(self) -> f64 {
+        let max = self.0.max(self.1).max(self.2) as f64;
+        let min = self.0.min(self.1).min(self.2) as f64;
+        if max == 0.0 { 0.0 } else { (max - min) / max }
+    }
+
+    /// HSV hue in degrees, 0.0-360.0 (0.0 for greys).
+    pub fn hue(self) -> f64 {
+        let (r, g, b) = (self.0 as f64, self.1 as f64, self.2 as f64);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0.0 {
+            return 0.0;
+        }
+        let sector = if max == r { (g - b) / delta } else if max == g { (b - r) / delta + 2.0 } else { (r - g) / delta + 4.0 };
+        (sector * 60.0).rem_euclid(360.0)
+    }
+
+    /// Moves `amount` (0.0-1.0) of the way towards `target`.
+    pub fn mix(self, target: Rgb, amount: f64) -> Rgb {
+        let channel = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * amount).round() as u8;
+        Rgb(channel(self.0, target.0), channel(self.1, target.1), channel(self.2, target.2))
+    }
+
+    /// Parses "#rrggbb" (or "rrggbb").
+    pub fn from_hex(hex: &str) -> Option<Rgb> {
+        let hex = hex.trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// "rrggbb", for formats that don't want the '#' (Hyprland's `rgb(...)`).
+    pub fn strip(self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.strip())
+    }
+}
+
+const BLACK: Rgb = Rgb(0, 0, 0);
+const WHITE: Rgb = Rgb(255, 255, 255);
+
+/// The colors templates can use.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+    pub accent: Rgb,
+    pub colors: [Rgb; 16], // color0 = background, color7/15 = foreground, color8-15 brighter
+}
+
+// --- Median Cut ---
+
+/// One box of similar pixels.
+struct Bucket(Vec<[u8; 3]>);
+
+impl Bucket {
+    /// The channel with the widest spread, and that spread.
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let (min, max) = self.0.iter().fold((255, 0), |(min, max), p| (p[c].min(min), p[c].max(max)));
+                (c, max.saturating_sub(min))
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    }
+
+    fn average(&self) -> Rgb {
+        let sum = self.0.iter().fold([0u64; 3], |acc, p| [acc[0] + p[0] as u64, acc[1] + p[1] as u64, acc[2] + p[2] as u64]);
+        let n = self.0.len().max(1) as u64;
+        Rgb((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8)
+    }
+}
+
+/// Splits `pixels` into up to `count` boxes; returns each box's average color and pixel count.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<(Rgb, usize)> {
+    let mut buckets = vec![Bucket(pixels)];
+    while buckets.len() < count {
+        // Split the box with the most spread weighted by pixels: range alone keeps picking
+        // tiny boxes around small bright details while a big mixed box averages into mud.
+        let Some((index, (channel, range))) = buckets.iter()
+            .map(Bucket::widest_channel)
+            .enumerate()
+            .max_by_key(|&(i, (_, range))| range as usize * buckets[i].0.len())
+        else { break };
+        if range == 0 || buckets[index].0.len() < 2 {
+            break; // Every box is a single color: fewer swatches than asked for
+        }
+        let mut bucket = buckets.swap_remove(index).0;
+        bucket.sort_unstable_by_key(|p| p[channel]);
+        let upper = bucket.split_off(bucket.len() / 2);
+        buckets.push(Bucket(bucket));
+        buckets.push(Bucket(upper));
+    }
+    buckets.iter().map(|b| (b.average(), b.0.len())).collect()
+}
+
+// --- Palette ---
+
+/// Extracts the palette of an image, or of a video's poster frame.
+pub fn extract(wallpaper: &Path) -> Result<Palette> {
+    let img = if video::needs_ffmpeg(wallpaper) {
+        let poster = dirs::runtime_dir().unwrap_or_else(env::temp_dir).join("wp-palette-poster.jpg");
+        video::poster_frame(wallpaper, &poster, SAMPLE_SIZE)?;
+        let img = image::open(&poster);
+        let _ = std::fs::remove_file(&poster);
+        img
+    } else {
+        image::open(wallpaper) // GIFs: the first frame
+    }
+    .with_context(|| format!("Failed to read {}", wallpaper.display()))?;
+
+    let pixels = sample(&img);
+    anyhow::ensure!(!pixels.is_empty(), "{} has no opaque pixels", wallpaper.display());
+    Ok(from_swatches(median_cut(pixels, SWATCHES)))
+}
+
+/// The color covering most of `img` (the most populated of a few median-cut boxes).
+/// What `wp-daemon` stores per wallpaper, so `wp-select` can group them by color.
+pub fn dominant(img: &DynamicImage) -> Option<Rgb> {
+    median_cut(sample(img), 8).into_iter()
+        .filter(|&(_, n)| n > 0)
+        .max_by_key(|&(_, n)| n)
+        .map(|(color, _)| color)
+}
+
+/// The opaque pixels of a downscaled copy.
+fn sample(img: &DynamicImage) -> Vec<[u8; 3]> {
+    img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_rgba8()
+        .pixels()
+        .filter(|p| p[3] >= 128) // Transparent areas aren't part of the picture
+        .map(|p| [p[0], p[1], p[2]])
+        .collect()
+}
+
+fn hue_distance(a: f64, b: f64) -> f64 {
+    let d = (a - b).abs();
+    d.min(360.0 - d)
+}
+
+fn from_swatches(mut swatches: Vec<(Rgb, usize)>) -> Palette {
+    let total: usize = swatches.iter().map(|&(_, n)| n).sum();
+    swatches.sort_by(|a, b| a.0.luminance().total_cmp(&b.0.luminance()));
+
+    // Background and foreground are pushed towards black/white so text stays readable.
+    let darkest = swatches[0].0;
+    let lightest = swatches[swatches.len() - 1].0;
+    let background = if darkest.luminance() > 0.1 { darkest.mix(BLACK, 0.7) } else { darkest };
+    let foreground = if lightest.luminance() < 0.85 { lightest.mix(WHITE, 0.7) } else { lightest };
+
+    // Vivid and mid-bright, weighted by coverage: a 3-pixel red dot shouldn't be the accent.
+    // Coverage counts the whole hue family, since a large gradient (sky) spans many swatches.
+    let family = |color: Rgb| -> usize {
+        swatches.iter()
+            .filter(|(other, _)| other.saturation() > 0.15 && hue_distance(color.hue(), other.hue()) < 30.0)
+            .map(|&(_, n)| n)
+            .sum()
+    };
+    let score = |&(color, n): &(Rgb, usize)| {
+        let usable = if (0.2..0.85).contains(&color.luminance()) { 1.0 } else { 0.3 };
+        let coverage = family(color).max(n);
+        color.saturation() * (coverage as f64 / total as f64).sqrt() * usable
+    };
+    let accent = swatches.iter()
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+        .map(|&(color, _)| color)
+        .unwrap_or(foreground);
+
+    // color1-6: the most populated swatches between the extremes, in luminance order.
+    let mut middle: Vec<(Rgb, usize)> = swatches.get(1..swatches.len() - 1).unwrap_or_default().to_vec();
+    middle.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    middle.truncate(6);
+    middle.sort_by(|a, b| a.0.luminance().total_cmp(&b.0.luminance()));
+    let mut normal = [accent; 8];
+    normal[0] = background;
+    normal[7] = foreground;
+    for (slot, &(color, _)) in normal[1..7].iter_mut().zip(&middle) {
+        // Lift dark swatches so they're visible as text on the background
+        *slot = if color.luminance() < 0.3 { color.mix(WHITE, 0.3) } else { color };
+    }
+
+    let mut colors = [BLACK; 16];
+    for (i, &color) in normal.iter().enumerate() {
+        colors[i] = color;
+        colors[i + 8] = color.mix(WHITE, 0.25);
+    }
+    colors[15] = foreground;
+    Palette { background, foreground, accent, colors }
+}
+
+impl Palette {
+    /// Looks up a template variable: `background`, `foreground`, `accent` or `color0`-`color15`.
+    pub fn get(&self, name: &str) -> Option<Rgb> {
+        match name {
+            "background" => Some(self.background),
+            "foreground" => Some(self.foreground),
+            "accent" => Some(self.accent),
+            _ => {
+                let index: usize = name.strip_prefix("color")?.parse().ok()?;
+                self.colors.get(index).copied()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(v: u8) -> Rgb {
+        Rgb(v, v, v)
+    }
+
+    #[test]
+    fn median_cut_keeps_every_pixel() {
+        let pixels: Vec<[u8; 3]> = (0..=255).flat_map(|v| [[v, v / 2, 255 - v]; 3]).collect();
+        let swatches = median_cut(pixels, SWATCHES);
+        assert_eq!(swatches.len(), SWATCHES);
+        assert_eq!(swatches.iter().map(|&(_, n)| n).sum::<usize>(), 256 * 3);
+    }
+
+    #[test]
+    fn median_cut_stops_when_the_boxes_are_single_colors() {
+        let pixels = [[[10, 20, 30]; 5], [[200, 100, 0]; 5]].concat();
+        let mut swatches = median_cut(pixels, SWATCHES);
+        swatches.sort_by_key(|&(color, _)| color.0);
+        assert_eq!(swatches, [(Rgb(10, 20, 30), 5), (Rgb(200, 100, 0), 5)]);
+    }
+
+    #[test]
+    fn from_swatches_orders_roles_by_luminance() {
+        let swatches = vec![(grey(150), 10), (grey(20), 10), (Rgb(40, 90, 200), 30), (grey(240), 10), (grey(90), 10)];
+        let palette = from_swatches(swatches);
+        assert_eq!(palette.background, grey(20)); // Already dark and light enough: unchanged
+        assert_eq!(palette.foreground, grey(240));
+        assert_eq!(palette.colors[0], palette.background);
+        assert_eq!(palette.colors[7], palette.foreground);
+        assert_eq!(palette.colors[15], palette.foreground);
+        // The three swatches in between, darkest first; the slots left over get the accent.
+        let middle: Vec<f64> = palette.colors[1..4].iter().map(|c| c.luminance()).collect();
+        assert!(middle.is_sorted(), "{:?}", palette.colors);
+        assert_eq!(palette.colors[4..7], [palette.accent; 3]);
+        for i in 0..7 {
+            assert!(palette.colors[i + 8].luminance() >= palette.colors[i].luminance());
+        }
+    }
+
+    #[test]
+    fn from_swatches_keeps_text_readable_on_a_washed_out_image() {
+        // Nothing dark or light in the image: both ends are pushed out.
+        let palette = from_swatches((100..=160).step_by(10).map(|v| (grey(v), 10)).collect());
+        assert!(palette.background.luminance() < 0.15, "{}", palette.background);
+        assert!(palette.foreground.luminance() > 0.85, "{}", palette.foreground);
+        for color in &palette.colors[1..7] {
+            assert!(color.luminance() >= 0.3, "{} is too dark to read on {}", color, palette.background);
+        }
+    }
+
+    #[test]
+    fn from_swatches_prefers_a_large_vivid_area_for_the_accent() {
+        let sky = Rgb(60, 120, 220);
+        let dot = Rgb(250, 0, 0);
+        let palette = from_swatches(vec![(grey(10), 500), (sky, 400), (dot, 3), (grey(230), 100)]);
+        assert_eq!(palette.accent, sky);
+    }
+
+    #[test]
+    fn from_swatches_handles_a_single_color() {
+        let palette = from_swatches(vec![(grey(128), 1)]);
+        assert!(palette.background.luminance() < palette.foreground.luminance());
+    }
+
+    #[test]
+    fn get_names_every_role_and_color() {
+        let palette = from_swatches((0..16).map(|v| (grey(v * 16), 1)).collect());
+        assert_eq!(palette.get("background"), Some(palette.background));
+        assert_eq!(palette.get("accent"), Some(palette.accent));
+        assert_eq!(palette.get("color15"), Some(palette.colors[15]));
+        assert_eq!(palette.get("color16"), None);
+        assert_eq!(palette.get("colour1"), None);
+    }
+}
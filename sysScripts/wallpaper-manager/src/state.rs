@@ -0,0 +1,143 @@
+//! Applied Wallpaper State
+//!
+//! `wp-apply` records what it put on every output, per compositor, so `wp-apply --restore`
+//! can bring all of them back at session start through the matching backend. Kept in the
+//! XDG state dir (not the cache): clearing caches shouldn't lose your wallpapers.
+//!
+//! `update_json` is the locked load-modify-write behind it, shared with wp-rotate's schedule.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// How an image is fitted to the output. Each backend maps it to its own flag.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    #[default]
+    Fill,    // Cover the output, cropping the overflow
+    Fit,     // Whole image visible, letterboxed
+    Stretch, // Ignore the aspect ratio
+    Center,  // Unscaled, centered
+    Tile,    // Unscaled, repeated
+}
+
+impl Fit {
+    /// The `swaybg -m` mode.
+    pub fn swaybg_mode(self) -> &'static str {
+        match self {
+            Fit::Fill => "fill",
+            Fit::Fit => "fit",
+            Fit::Stretch => "stretch",
+            Fit::Center => "center",
+            Fit::Tile => "tile",
+        }
+    }
+
+    /// The `swww img --resize` mode (swww can't tile; unscaled is the closest).
+    pub fn swww_resize(self) -> &'static str {
+        match self {
+            Fit::Fill => "crop",
+            Fit::Fit => "fit",
+            Fit::Stretch => "stretch",
+            Fit::Center | Fit::Tile => "no",
+        }
+    }
+
+    /// Extra mpv options for videos (mpv letterboxes by default).
+    pub fn mpv_options(self) -> &'static str {
+        match self {
+            Fit::Fill => "panscan=1.0",
+            Fit::Fit => "",
+            Fit::Stretch => "keepaspect=no",
+            Fit::Center | Fit::Tile => "video-unscaled=yes",
+        }
+    }
+}
+
+impl FromStr for Fit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fill" => Ok(Fit::Fill),
+            "fit" => Ok(Fit::Fit),
+            "stretch" => Ok(Fit::Stretch),
+            "center" => Ok(Fit::Center),
+            "tile" => Ok(Fit::Tile),
+            _ => anyhow::bail!("Unknown fit mode '{}' (fill, fit, stretch, center or tile)", s),
+        }
+    }
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.swaybg_mode())
+    }
+}
+
+/// What is on one output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Applied {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub fit: Fit,
+}
+
+/// compositor -> output -> wallpaper. Sorted maps keep the file diff-friendly.
+pub type AppliedState = BTreeMap<String, BTreeMap<String, Applied>>;
+
+fn state_file() -> Result<PathBuf> {
+    let dir = dirs::state_dir()
+        .or_else(dirs::cache_dir)
+        .context("Cannot find a state directory")?;
+    Ok(dir.join("wallpaper-manager/applied.json"))
+}
+
+/// Reads a JSON state file; empty if there is none yet (or it can't be read).
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> T {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Load-modify-write of a JSON state file under an exclusive lock, for files that several
+/// processes write (wp-rotate and wp-select can apply at the same moment). The lock is on a
+/// sibling file: the rename below replaces the file itself, and a lock on the replaced inode
+/// would protect nothing.
+pub fn update_json<T>(path: &Path, change: impl FnOnce(&mut T)) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lock = File::create(path.with_extension("json.lock"))?;
+    lock.lock().with_context(|| format!("Failed to lock {}", path.display()))?;
+
+    let mut value = load_json(path);
+    change(&mut value);
+    // Temp file + rename: a half-written state would lose every entry at once.
+    let tmp = path.with_extension(format!("json.{}.tmp", process::id()));
+    fs::write(&tmp, serde_json::to_string_pretty(&value)?)?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to save {}", path.display()))?;
+    Ok(()) // Dropping `lock` releases it
+}
+
+/// The recorded state; empty if there is none yet (or it can't be read).
+pub fn load() -> AppliedState {
+    state_file().map(|path| load_json(&path)).unwrap_or_default()
+}
+
+/// Records `applied` for one output, keeping every other output's entry.
+pub fn record(compositor: &str, output: &str, applied: Applied) -> Result<()> {
+    update_json(&state_file()?, |state: &mut AppliedState| {
+        state.entry(compositor.to_string()).or_default().insert(output.to_string(), applied);
+    })
+}
@@ -0,0 +1,170 @@
+//! Theme Generation
+//!
+//! When a wallpaper is applied, renders the user's templates with its palette (see
+//! `palette`) and runs their reload commands, so the bar, launcher, notifications and window
+//! borders follow the wallpaper on every compositor.
+//!
+//! Templates are plain files with `{{name}}` placeholders:
+//!   {{background}} {{foreground}} {{accent}} {{color0}}..{{color15}}  => "#rrggbb"
+//!   {{<color>.strip}} => "rrggbb"    {{<color>.rgb}} => "r, g, b"    {{wallpaper}} => image path
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::palette::{self, Palette};
+
+/// `[wallpaper_manager.theme]`
+#[derive(Deserialize, Debug, Default)]
+pub struct ThemeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub templates: Vec<Template>,
+}
+
+/// `[[wallpaper_manager.theme.templates]]`
+#[derive(Deserialize, Debug)]
+pub struct Template {
+    pub template: String,       // The source, with placeholders
+    pub output: String,         // Where the rendered file goes (what the app includes)
+    pub reload: Option<String>, // Shell command run afterwards (once, even if shared)
+}
+
+fn expand_path(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
+    }
+    PathBuf::from(path)
+}
+
+/// Replaces every `{{...}}` in `text`. Unknown names are an error, so typos don't ship.
+pub fn render(text: &str, palette: &Palette, wallpaper: &Path) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").context("Unclosed '{{' in template")? + start;
+        let name = rest[start + 2..end].trim();
+        if name == "wallpaper" {
+            out.push_str(&wallpaper.to_string_lossy());
+        } else {
+            let (color, format) = name.split_once('.').unwrap_or((name, ""));
+            let rgb = palette.get(color).with_context(|| format!("Unknown template variable '{}'", name))?;
+            match format {
+                "" => out.push_str(&rgb.to_string()),
+                "strip" => out.push_str(&rgb.strip()),
+                "rgb" => out.push_str(&format!("{}, {}, {}", rgb.0, rgb.1, rgb.2)),
+                _ => anyhow::bail!("Unknown format '{}' in '{}' (strip or rgb)", format, name),
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Renders one template to its output (temp file + rename: apps may be watching it).
+fn write_template(template: &Template, palette: &Palette, wallpaper: &Path) -> Result<()> {
+    let source = expand_path(&template.template);
+    let text = fs::read_to_string(&source)
+        .with_context(|| format!("Failed to read template {}", source.display()))?;
+    let rendered = render(&text, palette, wallpaper)
+        .with_context(|| format!("In template {}", source.display()))?;
+    let output = expand_path(&template.output);
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = output.with_extension("tmp");
+    fs::write(&tmp, rendered)?;
+    fs::rename(&tmp, &output).with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(())
+}
+
+/// Extracts `wallpaper`'s palette, renders every template and runs the reload commands.
+/// A broken template is reported and skipped; the others still apply.
+pub fn generate(config: &ThemeConfig, wallpaper: &Path) -> Result<()> {
+    let palette = palette::extract(wallpaper)?;
+    println!("Palette: background {}, foreground {}, accent {}", palette.background, palette.foreground, palette.accent);
+
+    let mut failed = 0;
+    let mut reloads: Vec<&str> = Vec::new();
+    for template in &config.templates {
+        match write_template(template, &palette, wallpaper) {
+            Ok(()) => {
+                if let Some(reload) = template.reload.as_deref()
+                    && !reloads.contains(&reload)
+                {
+                    reloads.push(reload);
+                }
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    // Exit codes are ignored: "waybar isn't running" is not an error worth reporting.
+    for reload in reloads {
+        if let Err(e) = Command::new("sh").arg("-c").arg(reload).stdin(Stdio::null()).status() {
+            eprintln!("Failed to run '{}': {}", reload, e);
+        }
+    }
+    anyhow::ensure!(failed == 0, "{} theme template(s) failed", failed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Rgb;
+
+    fn palette() -> Palette {
+        let mut colors = [Rgb(0, 0, 0); 16];
+        colors[4] = Rgb(0x12, 0x34, 0x56);
+        Palette { background: Rgb(0x10, 0x20, 0x30), foreground: Rgb(0xee, 0xee, 0xee), accent: Rgb(255, 128, 0), colors }
+    }
+
+    fn render_str(text: &str) -> Result<String> {
+        render(text, &palette(), Path::new("/w/sky.jpg"))
+    }
+
+    #[test]
+    fn render_fills_colors_formats_and_the_wallpaper() {
+        assert_eq!(render_str("bg={{background}} fg={{ foreground }}").unwrap(), "bg=#102030 fg=#eeeeee");
+        assert_eq!(render_str("rgb({{accent.strip}}) rgba({{accent.rgb}}, 0.5)").unwrap(), "rgb(ff8000) rgba(255, 128, 0, 0.5)");
+        assert_eq!(render_str("{{color4}}{{color0}}").unwrap(), "#123456#000000");
+        assert_eq!(render_str("image = {{wallpaper}}\n").unwrap(), "image = /w/sky.jpg\n");
+    }
+
+    #[test]
+    fn render_leaves_other_text_alone() {
+        let css = "window { color: red; }\n* {}\n";
+        assert_eq!(render_str(css).unwrap(), css);
+        assert_eq!(render_str("").unwrap(), "");
+    }
+
+    #[test]
+    fn render_rejects_unclosed_placeholders() {
+        let e = render_str("a {{accent} b").unwrap_err();
+        assert!(e.to_string().contains("Unclosed"), "{}", e);
+    }
+
+    #[test]
+    fn render_rejects_unknown_variables() {
+        for name in ["acent", "color16", "color", "wallpaper.strip"] {
+            let e = render_str(&format!("{{{{{}}}}}", name)).unwrap_err();
+            assert!(e.to_string().contains("Unknown template variable"), "{}: {}", name, e);
+        }
+    }
+
+    #[test]
+    fn render_rejects_unknown_formats() {
+        let e = render_str("{{accent.hsl}}").unwrap_err();
+        assert!(e.to_string().contains("Unknown format 'hsl'"), "{}", e);
+    }
+}
@@ -0,0 +1,118 @@
+//! Video & Animated Wallpapers
+//!
+//! Videos (and GIFs) are played by `mpvpaper`, one process per output. Each instance gets an
+//! mpv IPC socket named after its output, which is how a single output's player is found
+//! again (to replace or stop it) and how `wp-daemon` pauses playback while on battery.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::{Context, Result};
+
+/// Extensions handed to mpvpaper instead of an image backend.
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "avi", "gif"];
+
+fn extension(path: &Path) -> String {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+/// True for files played by mpvpaper (GIFs included: swww/swaybg would only show one frame).
+pub fn is_video(path: &Path) -> bool {
+    VIDEO_EXTENSIONS.contains(&extension(path).as_str())
+}
+
+/// True for formats the `image` crate can't decode, so their poster frame needs ffmpeg.
+pub fn needs_ffmpeg(path: &Path) -> bool {
+    is_video(path) && extension(path) != "gif"
+}
+
+/// Extracts a representative frame of a video with ffmpeg (its `thumbnail` filter skips
+/// black intro frames), scaled to `width`.
+pub fn poster_frame(video_path: &Path, out: &Path, width: u32) -> Result<()> {
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-i"])
+        .arg(video_path)
+        .args(["-vf", &format!("thumbnail,scale={}:-2", width), "-frames:v", "1"])
+        .arg(out)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to run ffmpeg (needed for video posters)")?;
+    anyhow::ensure!(status.success(), "ffmpeg could not extract a frame");
+    Ok(())
+}
+
+/// A video's pixel size, from ffprobe (installed with ffmpeg).
+pub fn dimensions(video_path: &Path) -> Option<(u32, u32)> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0", "-show_entries", "stream=width,height", "-of", "csv=p=0:s=x"])
+        .arg(video_path)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let (width, height) = text.trim().split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn runtime_dir() -> PathBuf {
+    dirs::runtime_dir().unwrap_or_else(std::env::temp_dir)
+}
+
+/// The mpv IPC socket for the player on `output`.
+pub fn ipc_socket(output: &str) -> PathBuf {
+    runtime_dir().join(format!("wp-mpvpaper-{}.sock", output))
+}
+
+/// Every player socket that currently exists.
+pub fn ipc_sockets() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(runtime_dir()) else { return Vec::new() };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            name.starts_with("wp-mpvpaper-") && name.ends_with(".sock")
+        })
+        .collect()
+}
+
+/// Sends one JSON command to an mpv IPC socket.
+fn send(socket: &Path, command: &str) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")
+}
+
+/// Pauses or resumes the player behind `socket`. A dead socket (player gone) is removed.
+pub fn set_paused(socket: &Path, paused: bool) {
+    let command = format!(r#"{{"command":["set_property","pause",{}]}}"#, paused);
+    if send(socket, &command).is_err() {
+        let _ = fs::remove_file(socket);
+    }
+}
+
+/// Stops the player on `output`, if any. Other outputs keep playing.
+pub fn stop(output: &str) {
+    let socket = ipc_socket(output);
+    if socket.exists() {
+        let _ = send(&socket, r#"{"command":["quit"]}"#);
+        let _ = fs::remove_file(&socket);
+    }
+}
+
+/// True if the machine is running on battery: there is a mains supply and it is offline.
+/// Desktops without any power-supply info count as plugged in.
+pub fn on_battery() -> bool {
+    let Ok(entries) = fs::read_dir("/sys/class/power_supply") else { return false };
+    let mut mains = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| fs::read_to_string(p.join("type")).is_ok_and(|t| t.trim() == "Mains"))
+        .peekable();
+    if mains.peek().is_none() {
+        return false;
+    }
+    !mains.any(|p| fs::read_to_string(p.join("online")).is_ok_and(|o| o.trim() == "1"))
+}
@@ -0,0 +1,335 @@
+//! Layered Waybar config composition.
+//!
+//! Reads a stack of JSONC files (base -> compositor -> extra overlays) and deep-merges
+//! them into a single config value, so shared modules only have to be defined once.
+//!
+//! Merge rules, applied from the bottom layer up:
+//! - **Objects** merge key by key, recursively.
+//! - **Arrays** are replaced wholesale by the overlay (e.g. `modules-right`).
+//!   To extend the lower layer instead, suffix the key with `+`:
+//!   `"modules-right+": ["custom/vpn"]` appends to the inherited `modules-right`.
+//! - **`null`** removes the key from the result (e.g. drop a module definition).
+//! - **Scalars** (strings, numbers, bools) are replaced by the overlay.
+//! - A top-level **array of bars** merges element-wise by index; a top-level object
+//!   is treated as a single-bar array when combined with one, so it only merges into
+//!   the *first* bar (use an array of the same length to change the others).
+//!
+//! With per-output generation enabled, the composed bar(s) are then stamped out once per
+//! active output, and that output's profile from config.toml is merged on top using the
+//! same rules (so a profile can replace `modules-right` or append with `modules-right+`).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+/// Strips `//` and `/* */` comments plus trailing commas so serde_json can parse Waybar's JSONC.
+/// String literals are copied verbatim, so URLs like "https://..." survive.
+fn strip_jsonc(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        out.push(escaped);
+                    }
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                // Line comment: skip to end of line, keep the newline for error line numbers.
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        out.push('\n');
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = '\0';
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        out.push('\n');
+                    }
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    remove_trailing_commas(&out)
+}
+
+/// Drops commas that are directly followed (ignoring whitespace) by `]` or `}`.
+fn remove_trailing_commas(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = String::with_capacity(input.len());
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if in_string {
+            out.push(c);
+            if c == '\\' && i + 1 < chars.len() {
+                out.push(chars[i + 1]);
+                i += 1;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+            out.push(c);
+        } else if c == ',' {
+            let next = chars[i + 1..].iter().find(|n| !n.is_whitespace());
+            if !matches!(next, Some(']') | Some('}')) {
+                out.push(c);
+            }
+        } else {
+            out.push(c);
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Parses a JSONC string into a JSON value.
+pub fn parse_jsonc(input: &str) -> Result<Value> {
+    Ok(serde_json::from_str(&strip_jsonc(input))?)
+}
+
+/// Reads and parses a single JSONC layer from disk.
+pub fn load_layer(path: &Path) -> Result<Value> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Waybar config layer {:?}", path))?;
+    parse_jsonc(&content)
+        .with_context(|| format!("Failed to parse Waybar config layer {:?}", path))
+}
+
+/// Merges `overlay` into `base` following the rules in the module docs.
+fn merge_value(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base_map), Value::Object(overlay_map)) => merge_object(base_map, overlay_map),
+        // Nothing to merge into: start from an empty object so nested `+`/`null` markers
+        // are still applied instead of ending up in the generated config.
+        (base, Value::Object(overlay_map)) => {
+            let mut fresh = Map::new();
+            merge_object(&mut fresh, overlay_map);
+            *base = Value::Object(fresh);
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// An overlay value with no counterpart below it, with its markers applied.
+fn resolve(overlay: Value) -> Value {
+    let mut value = Value::Null;
+    merge_value(&mut value, overlay);
+    value
+}
+
+fn merge_object(base: &mut Map<String, Value>, overlay: Map<String, Value>) {
+    for (key, value) in overlay {
+        // "key+": append to the inherited array instead of replacing it.
+        if let Some(target_key) = key.strip_suffix('+') {
+            match (base.get_mut(target_key), value) {
+                (Some(Value::Array(existing)), Value::Array(extra)) => existing.extend(extra),
+                (_, value) => {
+                    base.insert(target_key.to_string(), resolve(value));
+                }
+            }
+            continue;
+        }
+        if value.is_null() {
+            base.remove(&key);
+            continue;
+        }
+        match base.get_mut(&key) {
+            Some(existing) => merge_value(existing, value),
+            None => {
+                base.insert(key, resolve(value));
+            }
+        }
+    }
+}
+
+/// Merges two top-level configs. Waybar accepts either one bar object or an array of bars.
+fn merge_top_level(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Array(mut base_bars), Value::Array(overlay_bars)) => {
+            for (i, bar) in overlay_bars.into_iter().enumerate() {
+                match base_bars.get_mut(i) {
+                    Some(existing) => merge_value(existing, bar),
+                    None => base_bars.push(resolve(bar)),
+                }
+            }
+            Value::Array(base_bars)
+        }
+        // A lone object is bar 0; bars 1..n are left as they are.
+        (Value::Array(base_bars), overlay @ Value::Object(_)) => {
+            merge_top_level(Value::Array(base_bars), Value::Array(vec![overlay]))
+        }
+        (base @ Value::Object(_), Value::Array(overlay_bars)) => {
+            merge_top_level(Value::Array(vec![base]), Value::Array(overlay_bars))
+        }
+        (mut base, overlay) => {
+            merge_value(&mut base, overlay);
+            base
+        }
+    }
+}
+
+/// Loads every layer in order and folds them into one config.
+pub fn compose(layers: &[&Path]) -> Result<Value> {
+    let mut merged: Option<Value> = None;
+    for path in layers {
+        let layer = load_layer(path)?;
+        merged = Some(match (merged, layer) {
+            (Some(base), layer) => merge_top_level(base, layer),
+            (None, Value::Array(bars)) => Value::Array(bars.into_iter().map(resolve).collect()),
+            (None, layer) => resolve(layer),
+        });
+    }
+    merged.context("No Waybar config layers to compose")
+}
+
+/// Expands `config` into one copy of every bar per output, pinned with `"output"`.
+/// Each copy gets the profile named after its output, or the `"*"` profile if there is none.
+pub fn per_output(config: Value, outputs: &[String], profiles: &HashMap<String, Value>) -> Value {
+    let bars = match config {
+        Value::Array(bars) => bars,
+        bar => vec![bar],
+    };
+    let mut expanded = Vec::with_capacity(bars.len() * outputs.len());
+    for output in outputs {
+        for bar in &bars {
+            let mut bar = bar.clone();
+            if let Some(profile) = profiles.get(output).or_else(|| profiles.get("*")) {
+                merge_value(&mut bar, profile.clone());
+            }
+            if let Value::Object(map) = &mut bar {
+                map.insert("output".to_string(), Value::String(output.clone()));
+            }
+            expanded.push(bar);
+        }
+    }
+    Value::Array(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strip_jsonc_keeps_comment_markers_inside_strings() {
+        let input = r#"{
+            // a comment
+            "url": "https://example.com", /* block
+            comment */ "glob": "a/*b*/c",
+            "quote": "say \"// hi\""
+        }"#;
+        assert_eq!(parse_jsonc(input).unwrap(), json!({
+            "url": "https://example.com",
+            "glob": "a/*b*/c",
+            "quote": "say \"// hi\"",
+        }));
+    }
+
+    #[test]
+    fn strip_jsonc_drops_trailing_commas_but_not_commas_in_strings() {
+        let input = r#"{ "modules": ["clock", "tray",
+            ], "format": "{},}", }"#;
+        assert_eq!(parse_jsonc(input).unwrap(), json!({ "modules": ["clock", "tray"], "format": "{},}" }));
+    }
+
+    #[test]
+    fn strip_jsonc_keeps_line_numbers() {
+        let stripped = strip_jsonc("{ /* one\ntwo */\n// three\n\"a\": 1 }");
+        assert_eq!(stripped.lines().count(), 4);
+    }
+
+    #[test]
+    fn objects_merge_and_scalars_replace() {
+        let mut base = json!({ "clock": { "format": "{:%H:%M}", "interval": 60 }, "height": 30 });
+        merge_value(&mut base, json!({ "clock": { "format": "{:%R}" }, "height": 24 }));
+        assert_eq!(base, json!({ "clock": { "format": "{:%R}", "interval": 60 }, "height": 24 }));
+    }
+
+    #[test]
+    fn arrays_replace_unless_appended() {
+        let mut base = json!({ "modules-left": ["a"], "modules-right": ["b"] });
+        merge_value(&mut base, json!({ "modules-left": ["x"], "modules-right+": ["c"] }));
+        assert_eq!(base, json!({ "modules-left": ["x"], "modules-right": ["b", "c"] }));
+    }
+
+    #[test]
+    fn null_removes_a_key() {
+        let mut base = json!({ "tray": { "spacing": 4 }, "clock": {} });
+        merge_value(&mut base, json!({ "tray": null }));
+        assert_eq!(base, json!({ "clock": {} }));
+    }
+
+    #[test]
+    fn markers_apply_when_the_base_has_no_such_key() {
+        let mut base = json!({});
+        merge_value(&mut base, json!({ "custom/vpn": { "exec+": ["vpn"], "tooltip": null, "format": "{}" } }));
+        assert_eq!(base, json!({ "custom/vpn": { "exec": ["vpn"], "format": "{}" } }));
+    }
+
+    #[test]
+    fn markers_apply_in_the_first_layer_and_new_bars() {
+        let merged = merge_top_level(json!([{ "layer": "top" }]), json!([{}, { "modules+": ["a"], "x": null }]));
+        assert_eq!(merged, json!([{ "layer": "top" }, { "modules": ["a"] }]));
+    }
+
+    #[test]
+    fn object_overlay_merges_into_the_first_bar_only() {
+        let merged = merge_top_level(
+            json!([{ "height": 30 }, { "height": 30, "position": "bottom" }, { "height": 40 }]),
+            json!({ "height": 24, "layer": "top" }),
+        );
+        assert_eq!(merged, json!([
+            { "height": 24, "layer": "top" },
+            { "height": 30, "position": "bottom" },
+            { "height": 40 },
+        ]));
+    }
+
+    #[test]
+    fn object_base_becomes_the_first_bar_of_an_array_overlay() {
+        let merged = merge_top_level(json!({ "height": 30 }), json!([{ "layer": "top" }, { "height": 20 }]));
+        assert_eq!(merged, json!([{ "height": 30, "layer": "top" }, { "height": 20 }]));
+    }
+
+    #[test]
+    fn per_output_pins_each_bar_and_applies_profiles() {
+        let profiles = HashMap::from([
+            ("HDMI-A-1".to_string(), json!({ "modules-right+": ["tray"] })),
+            ("*".to_string(), json!({ "height": 20 })),
+        ]);
+        let expanded = per_output(json!({ "modules-right": ["clock"] }), &["eDP-1".into(), "HDMI-A-1".into()], &profiles);
+        assert_eq!(expanded, json!([
+            { "modules-right": ["clock"], "height": 20, "output": "eDP-1" },
+            { "modules-right": ["clock", "tray"], "output": "HDMI-A-1" },
+        ]));
+    }
+}
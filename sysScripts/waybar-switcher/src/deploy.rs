@@ -0,0 +1,57 @@
+//! Safe deployment of the generated config.
+//!
+//! The live target is only ever replaced by a `rename(2)` from a fully written temp file
+//! in the same directory, so Waybar (or a crash mid-write) never sees a half-written config.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Sanity-checks a composed config before it is allowed anywhere near the live target.
+/// Waybar accepts either a single bar object or an array of bar objects.
+pub fn validate(config: &Value) -> Result<()> {
+    let bars: Vec<&Value> = match config {
+        Value::Array(bars) if bars.is_empty() => anyhow::bail!("Config is an empty array of bars"),
+        Value::Array(bars) => bars.iter().collect(),
+        Value::Object(_) => vec![config],
+        _ => anyhow::bail!("Config must be a bar object or an array of bar objects"),
+    };
+
+    for (i, bar) in bars.iter().enumerate() {
+        let bar = bar.as_object()
+            .with_context(|| format!("Bar #{} is not an object", i))?;
+        for key in ["modules-left", "modules-center", "modules-right"] {
+            let Some(modules) = bar.get(key) else { continue };
+            let modules = modules.as_array()
+                .with_context(|| format!("Bar #{}: '{}' must be an array", i, key))?;
+            if let Some(bad) = modules.iter().find(|m| !m.is_string()) {
+                anyhow::bail!("Bar #{}: '{}' contains a non-string entry: {}", i, key, bad);
+            }
+        }
+        if let Some(include) = bar.get("include")
+            && !(include.is_string() || include.as_array().is_some_and(|a| a.iter().all(Value::is_string)))
+        {
+            anyhow::bail!("Bar #{}: 'include' must be a path or an array of paths", i);
+        }
+    }
+    Ok(())
+}
+
+/// Atomically replaces `path` with `contents` (write temp file -> fsync -> rename).
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name()
+        .with_context(|| format!("Target path {:?} has no file name", path))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("Failed to create temp file {:?}", tmp_path))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write temp file {:?}", tmp_path))?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to move {:?} into place at {:?}", tmp_path, path))?;
+    Ok(())
+}
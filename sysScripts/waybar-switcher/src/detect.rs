@@ -0,0 +1,49 @@
+//! Session detection.
+//!
+//! Each rule names a session, the signals that identify it and the bar config to use.
+//! User rules from `[[waybar_switcher.rules]]` are checked before the built-in
+//! Niri / Hyprland / Sway rules, so any of them can be overridden or extended
+//! (river, labwc, wayfire, GNOME...) without recompiling.
+
+use std::env;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DetectionRule {
+    pub name: String,            // Session name, used in logs and for per-session behaviour
+    pub env: Option<String>,     // Matches if this environment variable is set (e.g. an IPC socket)
+    pub desktop: Option<String>, // Matches if XDG_CURRENT_DESKTOP contains this (case-insensitive)
+    pub config: String,          // Waybar source file for this session
+}
+
+impl DetectionRule {
+    /// Shorthand for the built-in rules, which keep their dedicated config keys.
+    pub fn builtin(name: &str, env: &str, config: &str) -> Self {
+        DetectionRule {
+            name: name.to_string(),
+            env: Some(env.to_string()),
+            desktop: Some(name.to_string()),
+            config: config.to_string(),
+        }
+    }
+
+    /// A rule with neither signal can never match, which is almost certainly a typo.
+    pub fn is_usable(&self) -> bool {
+        self.env.is_some() || self.desktop.is_some()
+    }
+}
+
+/// Picks the first matching rule.
+///
+/// I check every rule's environment variable before looking at any `XDG_CURRENT_DESKTOP`
+/// substring, because the latter is sometimes set incorrectly by display managers or
+/// previous sessions while IPC socket variables are only present in the real session.
+pub fn detect(rules: &[DetectionRule]) -> Option<&DetectionRule> {
+    if let Some(rule) = rules.iter().find(|r| r.env.as_ref().is_some_and(|var| env::var_os(var).is_some())) {
+        return Some(rule);
+    }
+    let desktop = env::var("XDG_CURRENT_DESKTOP").ok()?.to_lowercase();
+    rules.iter().find(|r| {
+        r.desktop.as_ref().is_some_and(|needle| desktop.contains(&needle.to_lowercase()))
+    })
+}
@@ -1,31 +1,71 @@
 //! Waybar Configuration Switcher
 //!
 //! A system utility that automatically detects the running Wayland compositor 
-//! (Niri, Hyprland, or Sway) and hot-swaps the corresponding Waybar configuration file.
+//! (Niri, Hyprland, Sway, or any session described by a rule in config.toml)
+//! and hot-swaps the corresponding Waybar configuration file.
 //!
 //! This solves the problem of using a single status bar across multiple window managers
 //! where layout requirements (modules, workspaces) differ significantly.
+//!
+//! The active config is composed from layers (see `compose`): an optional shared
+//! `base_config`, the compositor-specific file, then any extra `overlays`.
+//!
+//! Swaps are transactional: the composed config is validated before it touches the
+//! live target, written atomically, and rolled back if the restarted bar dies within
+//! `grace_period_ms`.
+//!
+//! With `per_output = true` the composed bar is repeated once per active output, each copy
+//! pinned with `"output"` and customised by its entry in `output_profiles`.
+//!
+//! Usage:
+//!   waybar-switcher          => Compose, deploy and (re)load the bar once.
+//!   waybar-switcher --watch  => Keep running and redeploy whenever a source layer,
+//!                               an included module file or a `style_files` entry changes,
+//!                               or (with `per_output`) when a monitor is plugged in or removed.
+
+mod compose;
+mod deploy;
+mod detect;
+mod outputs;
+mod waybar;
 
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use notify_rust::{Notification, Urgency};
 use serde::Deserialize;
+use serde_json::Value;
+use detect::DetectionRule;
 use std::env;
-use std::thread;
 use std::time::Duration;
 
+/// Quiet period used to coalesce the burst of events an editor save produces.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+/// How often --watch re-checks the output list for hotplugged monitors.
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 /// Expands the tilde (`~`) in file paths to the user's home directory.
 /// Rust's standard library `Path` does not handle shell expansions automatically.
 fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(stripped);
-        }
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(stripped);
     }
     PathBuf::from(path)
 }
 
+/// Expands the `$HOME` / `~` forms Waybar accepts in `include` paths.
+fn expand_include(path: &str) -> PathBuf {
+    match (path.strip_prefix("$HOME/"), dirs::home_dir()) {
+        (Some(stripped), Some(home)) => home.join(stripped),
+        _ => expand_path(path),
+    }
+}
+
 // --- Config Models ---
 
 #[derive(Deserialize, Debug)]
@@ -34,8 +74,35 @@ struct WaybarSwitcherConfig {
     niri_config: String,    // Source file for Niri
     hyprland_config: String,// Source file for Hyprland
     sway_config: String,    // Source file for Sway
+    base_config: Option<String>, // Shared layer merged underneath the compositor file
+    #[serde(default)]
+    overlays: Vec<String>,  // Extra layers merged on top, in order (e.g. machine-local tweaks)
+    #[serde(default = "default_grace_period_ms")]
+    grace_period_ms: u64,   // How long the new bar must survive before the swap is kept
+    #[serde(default)]
+    style_files: Vec<String>, // Extra files (CSS) that trigger a reload in --watch mode
+    #[serde(default)]
+    rules: Vec<DetectionRule>, // Extra sessions, checked before the built-in three
+    fallback_config: Option<String>, // Used when no rule matches; unset means "refuse to guess"
+    #[serde(default)]
+    per_output: bool,       // Emit one bar per active output instead of a single shared bar
+    #[serde(default)]
+    output_profiles: HashMap<String, Value>, // Output name (or "*") -> keys merged into that output's bar
 }
 
+impl WaybarSwitcherConfig {
+    /// User rules first so they can override the built-ins, then Niri, Hyprland and Sway.
+    fn detection_rules(&self) -> Vec<DetectionRule> {
+        let mut rules = self.rules.clone();
+        rules.push(DetectionRule::builtin("niri", "NIRI_SOCKET", &self.niri_config));
+        rules.push(DetectionRule::builtin("hyprland", "HYPRLAND_INSTANCE_SIGNATURE", &self.hyprland_config));
+        rules.push(DetectionRule::builtin("sway", "SWAYSOCK", &self.sway_config));
+        rules
+    }
+}
+
+fn default_grace_period_ms() -> u64 { 3000 }
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     waybar_switcher: WaybarSwitcherConfig,
@@ -56,74 +123,249 @@ fn load_config() -> Result<GlobalConfig> {
     Ok(config)
 }
 
-/// Identifies the active Wayland compositor by checking unique environment variables.
-///
-/// I prioritize socket variables (e.g., `SWAYSOCK`) over `XDG_CURRENT_DESKTOP`
-/// because the latter is sometimes set incorrectly by display managers or previous sessions.
-fn get_compositor() -> Option<String> {
-    // Check for specific IPC sockets first (most reliable method)
-    if env::var("NIRI_SOCKET").is_ok() {
-        return Some("niri".to_string());
-    }
-    if env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
-        return Some("hyprland".to_string());
-    }
-    if env::var("SWAYSOCK").is_ok() {
-        return Some("sway".to_string());
-    }
-    // Fallback: Check standard XDG variables
-    if let Ok(desktop) = env::var("XDG_CURRENT_DESKTOP") {
-        let desktop = desktop.to_lowercase();
-        if desktop.contains("niri") { return Some("niri".to_string()); }
-        if desktop.contains("hyprland") { return Some("hyprland".to_string()); }
-        if desktop.contains("sway") { return Some("sway".to_string()); }
-    }
-    None
+/// Pops up a desktop notification explaining why the bar could not be switched.
+fn notify_failure(body: &str) {
+    let _ = Notification::new()
+        .summary("Waybar Switcher")
+        .body(body)
+        .icon("dialog-error")
+        .urgency(Urgency::Critical)
+        .show();
 }
-fn main() -> Result<()> {
-    // 1. Load User Preferences
-    let global_config = load_config()?;
-    let config = global_config.waybar_switcher;
-    //2.Detect Environment
-    let compositor = get_compositor().unwrap_or_else(|| "unknown".to_string());
-    println!("Detected compositor: {}", compositor);
-    // 3. Select Config Source
+
+/// The session detected at startup.
+struct Session {
+    name: Option<String>, // Name of the matched rule; None when running on fallback_config
+    layers: Vec<PathBuf>, // Ordered layer stack (base -> session file -> overlays)
+}
+
+/// Detects the running session and resolves its ordered layer stack.
+fn detect_session(config: &WaybarSwitcherConfig) -> Result<Session> {
+    // 2. Detect Environment & Select Config Source
     // I map the detected environment to the specific source file defined in config.toml.
-    let source_path_str = match compositor.as_str() {
-        "niri" => &config.niri_config,
-        "hyprland" => &config.hyprland_config,
-        "sway" => &config.sway_config,
-        _ => {
-            println!("Unknown compositor, defaulting to Hyprland config.");
-            &config.hyprland_config
+    let rules = config.detection_rules();
+    if let Some(bad) = rules.iter().find(|r| !r.is_usable()) {
+        anyhow::bail!("Detection rule '{}' needs an `env` or `desktop` key", bad.name);
+    }
+    let matched = detect::detect(&rules);
+    let source_path_str = match matched {
+        Some(rule) => {
+            println!("Detected session: {}", rule.name);
+            &rule.config
+        }
+        None => {
+            let fallback = config.fallback_config.as_ref().context(
+                "No detection rule matched this session and no fallback_config is set",
+            )?;
+            println!("No detection rule matched, using fallback_config.");
+            fallback
         }
     };
     // Expand paths to handle `~/` notation from the TOML file
-    let source_path = expand_path(source_path_str);
+    // Layer order: base -> compositor -> overlays (later layers win).
+    let layers = config.base_config.iter()
+        .map(|p| expand_path(p))
+        .chain(std::iter::once(expand_path(source_path_str)))
+        .chain(config.overlays.iter().map(|p| expand_path(p)))
+        .collect();
+    Ok(Session { name: matched.map(|r| r.name.clone()), layers })
+}
+
+/// The outputs to generate bars for, or None when per-output generation is off.
+fn current_outputs(config: &WaybarSwitcherConfig, session: &Session) -> Result<Option<Vec<String>>> {
+    if !config.per_output {
+        return Ok(None);
+    }
+    let outputs = outputs::active(session.name.as_deref())
+        .context("Failed to list active outputs for per-output bars")?;
+    // With every output off (e.g. lid closed, DPMS) there is nothing to pin bars to,
+    // so I keep the plain composed config instead of emitting an empty bar list.
+    Ok(if outputs.is_empty() { None } else { Some(outputs) })
+}
+
+/// Composes the layers, swaps them into the target and brings the bar up on it.
+/// Returns the composed config so watch mode can follow its `include` files.
+fn deploy(config: &WaybarSwitcherConfig, session: &Session, outputs: Option<&[String]>) -> Result<Value> {
     let target_path = expand_path(&config.target_file);
 
-    println!("Copying config:\n  From: {:?}\n  To:   {:?}", source_path, target_path);
+    println!("Composing config:");
+    for layer in &session.layers {
+        println!("  Layer: {:?}", layer);
+    }
+    if let Some(outputs) = outputs {
+        println!("  For:   {}", outputs.join(", "));
+    }
+    println!("  To:    {:?}", target_path);
+
+    // 3. Compose & Validate
+    // Everything up to here is read-only: a broken source file aborts before the live
+    // target is touched, so the running bar keeps its last good config.
+    let layers: Vec<&Path> = session.layers.iter().map(PathBuf::as_path).collect();
+    let mut merged = compose::compose(&layers)?;
+    if let Some(outputs) = outputs {
+        merged = compose::per_output(merged, outputs, &config.output_profiles);
+    }
+    deploy::validate(&merged).context("Composed Waybar config failed validation")?;
+    let rendered = serde_json::to_string_pretty(&merged)
+        .context("Failed to serialize composed Waybar config")?;
 
-    // 4. Overwrite Active Configuration
+    // 4. Swap Active Configuration
     // We overwrite the target file rather than symlinking to avoid issues 
     // where file watchers might track the link target instead of the link itself.
-    fs::copy(&source_path, &target_path)
-        .with_context(|| format!("Failed to copy {:?} to {:?}", source_path, target_path))?;
-
-    // 5. Restart Waybar Process
-    println!("Restarting Waybar...");
-    // Kill existing instances to prevent duplicates or zombie processes.
-    // We ignore the result because it fails if Waybar isn't running, which is fine.
-    let _ = Command::new("pkill").arg("-x").arg("waybar").status();
-    // Brief sleep to ensure the socket is released by the OS before restarting.
-    thread::sleep(Duration::from_millis(500));
-    // Spawn new instance detached from this process
-    Command::new("waybar")
-        .arg("-c")
-        .arg(&target_path)
-        .spawn()
-        .context("Failed to spawn new waybar process")?;
-
-    println!("Waybar restarted successfully.");
+    // The previous contents are kept in memory for rollback.
+    let previous = fs::read(&target_path).ok();
+    deploy::write_atomic(&target_path, rendered.as_bytes())?;
+
+    // 5. Reload Waybar & Confirm It Stays Up
+    // SIGUSR2 keeps the existing process (and its tray) when it already runs on our target.
+    let mut instance = waybar::reload_or_restart(&target_path)?;
+    let grace = Duration::from_millis(config.grace_period_ms);
+    let Some(failure) = waybar::wait_for_crash(&mut instance, grace)? else {
+        println!("Waybar is up on the new config.");
+        instance.detach();
+        return Ok(merged);
+    };
+
+    // 6. Rollback
+    let reason = format!("{} with the new config:\n{}", failure, waybar::log_tail(5));
+    eprintln!("{}", reason);
+    match previous {
+        Some(previous) => {
+            deploy::write_atomic(&target_path, &previous)
+                .context("Failed to restore the previous Waybar config")?;
+            waybar::restart(&target_path)?.detach();
+            anyhow::bail!("{}\n\nRestored the previous config.", reason)
+        }
+        None => anyhow::bail!("{}\n\nNo previous config to restore.", reason),
+    }
+}
+
+/// Everything whose edits should trigger a redeploy in watch mode:
+/// the layers themselves, configured style files and every `include` of the composed config.
+fn watched_files(config: &WaybarSwitcherConfig, session: &Session, merged: Option<&Value>) -> HashSet<PathBuf> {
+    let mut files: HashSet<PathBuf> = session.layers.iter().cloned().collect();
+    files.extend(config.style_files.iter().map(|p| expand_path(p)));
+
+    let bars: Vec<&Value> = match merged {
+        Some(Value::Array(bars)) => bars.iter().collect(),
+        Some(bar) => vec![bar],
+        None => Vec::new(),
+    };
+    for bar in bars {
+        match bar.get("include") {
+            Some(Value::String(path)) => { files.insert(expand_include(path)); }
+            Some(Value::Array(paths)) => {
+                files.extend(paths.iter().filter_map(Value::as_str).map(expand_include));
+            }
+            _ => {}
+        }
+    }
+    files
+}
+
+/// Blocks until one of `files` is written, created or removed (then swallows the rest of the
+/// burst), or until the periodic `outputs_changed` check reports a hotplug.
+fn wait_for_change(
+    rx: &Receiver<notify::Result<notify::Event>>,
+    files: &HashSet<PathBuf>,
+    mut outputs_changed: impl FnMut() -> bool,
+) -> Result<()> {
+    let is_relevant = |event: &notify::Event| {
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+            && event.paths.iter().any(|p| files.contains(p))
+    };
+    loop {
+        match rx.recv_timeout(OUTPUT_POLL_INTERVAL) {
+            Ok(Ok(event)) if is_relevant(&event) => break,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("Watch error {:?}", e),
+            Err(RecvTimeoutError::Timeout) => {
+                if outputs_changed() {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped unexpectedly"),
+        }
+    }
+    // Debounce: editors often write, chmod and rename in quick succession.
+    loop {
+        match rx.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => return Ok(()),
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped unexpectedly"),
+        }
+    }
+}
+
+/// Watch mode: redeploy on every source edit, and on output hotplug when per-output bars
+/// are enabled. Failures are reported but never fatal, so a typo mid-edit just leaves
+/// the last good bar running.
+fn run_watch(config: &WaybarSwitcherConfig, session: &Session) -> Result<()> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut files = watched_files(config, session, None);
+    let mut outputs = current_outputs(config, session)?;
+
+    loop {
+        match deploy(config, session, outputs.as_deref()) {
+            Ok(merged) => files = watched_files(config, session, Some(&merged)),
+            Err(e) => {
+                eprintln!("Deploy failed: {:#}", e);
+                notify_failure(&format!("{:#}", e));
+            }
+        }
+
+        // Watch parent directories rather than the files themselves: most editors save by
+        // writing a new file and renaming it over the old one, which drops per-file watches.
+        for dir in files.iter().filter_map(|f| f.parent()) {
+            if dir.is_dir() && watched_dirs.insert(dir.to_path_buf()) {
+                watcher.watch(dir, RecursiveMode::NonRecursive)
+                    .with_context(|| format!("Failed to watch {:?}", dir))?;
+            }
+        }
+        println!("Watching {} files for changes...", files.len());
+        wait_for_change(&rx, &files, || {
+            // A failed query (compositor busy, CLI missing) is treated as "no change".
+            match current_outputs(config, session) {
+                Ok(now) if now != outputs => {
+                    println!("Outputs changed: {:?} -> {:?}", outputs, now);
+                    outputs = now;
+                    true
+                }
+                Ok(_) => false,
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    false
+                }
+            }
+        })?;
+        println!("Change detected, redeploying...");
+    }
+}
+
+fn run(watch: bool) -> Result<()> {
+    // 1. Load User Preferences
+    let config = load_config()?.waybar_switcher;
+    let session = detect_session(&config)?;
+    if watch {
+        return run_watch(&config, &session);
+    }
+    let outputs = current_outputs(&config, &session)?;
+    deploy(&config, &session, outputs.as_deref())?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let watch = match env::args().nth(1).as_deref() {
+        None => false,
+        Some("--watch") => true,
+        Some(other) => anyhow::bail!("Unknown argument '{}'. Use --watch or no arguments.", other),
+    };
+
+    if let Err(e) = run(watch) {
+        notify_failure(&format!("{:#}", e));
+        return Err(e);
+    }
     Ok(())
 }
@@ -0,0 +1,68 @@
+//! Active output discovery, used to generate one bar per monitor.
+//!
+//! Each compositor is asked through its own CLI (`swaymsg`, `hyprctl`, `niri msg`);
+//! any other session falls back to `wlr-randr`, which works on most wlroots compositors.
+
+use std::process::Command;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+struct SwayOutput {
+    name: String,
+    active: bool,
+}
+
+#[derive(Deserialize)]
+struct HyprMonitor {
+    name: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct WlrOutput {
+    name: String,
+    enabled: bool,
+}
+
+/// Runs a query command and returns its stdout, failing on a non-zero exit.
+fn query(program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!("{} {} failed", program, args.join(" "));
+    }
+    Ok(output.stdout)
+}
+
+/// Names of the enabled outputs for `session` (a detection rule name), sorted for stable output.
+pub fn active(session: Option<&str>) -> Result<Vec<String>> {
+    let mut names: Vec<String> = match session {
+        Some("sway") => {
+            let outputs: Vec<SwayOutput> = serde_json::from_slice(&query("swaymsg", &["-t", "get_outputs", "-r"])?)?;
+            outputs.into_iter().filter(|o| o.active).map(|o| o.name).collect()
+        }
+        Some("hyprland") => {
+            let monitors: Vec<HyprMonitor> = serde_json::from_slice(&query("hyprctl", &["-j", "monitors"])?)?;
+            monitors.into_iter().filter(|m| !m.disabled).map(|m| m.name).collect()
+        }
+        Some("niri") => {
+            // Niri returns a map of name -> output; disabled outputs have no logical size.
+            let outputs: serde_json::Map<String, Value> = serde_json::from_slice(&query("niri", &["msg", "--json", "outputs"])?)?;
+            outputs.into_iter()
+                .filter(|(_, o)| o.get("logical").is_some_and(|l| !l.is_null()))
+                .map(|(name, _)| name)
+                .collect()
+        }
+        _ => {
+            let outputs: Vec<WlrOutput> = serde_json::from_slice(&query("wlr-randr", &["--json"])?)?;
+            outputs.into_iter().filter(|o| o.enabled).map(|o| o.name).collect()
+        }
+    };
+    names.sort();
+    Ok(names)
+}
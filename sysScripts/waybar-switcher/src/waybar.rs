@@ -0,0 +1,146 @@
+//! Waybar process management.
+//!
+//! If a bar is already running on our target file, it is reloaded in place with
+//! `SIGUSR2` (keeps tray icons and avoids the startup flash). Otherwise any stray bar is
+//! killed and a fresh one is spawned.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+
+/// How the new config was brought up, which determines how we watch it afterwards.
+pub enum Instance {
+    /// We spawned this bar ourselves and can wait on it directly.
+    Spawned(Child),
+    /// An existing bar was told to reload; we can only poll for it by PID.
+    Reloaded(Vec<u32>),
+}
+
+impl Instance {
+    /// Lets a bar that survived the grace period run on its own.
+    /// A spawned child gets a reaper thread so a long-running `--watch` never collects zombies.
+    pub fn detach(self) {
+        if let Instance::Spawned(mut child) = self {
+            thread::spawn(move || child.wait());
+        }
+    }
+}
+
+/// Where Waybar's stderr is captured so a failed start can be explained to the user.
+pub fn log_path() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("waybar-switcher.log")
+}
+
+/// True if `pid` exists and is not a zombie waiting to be reaped.
+fn is_alive(pid: u32) -> bool {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) else { return false };
+    // The state letter follows the parenthesised command name, which may itself contain spaces.
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .is_some_and(|state| state != "Z" && state != "X")
+}
+
+/// PIDs of running `waybar` processes.
+fn running_pids() -> Vec<u32> {
+    Command::new("pgrep")
+        .arg("-x")
+        .arg("waybar")
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .filter_map(|l| l.trim().parse().ok())
+                .filter(|pid| is_alive(*pid))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// True if the process was started with `-c <config>` (or `--config <config>`).
+/// A bar on some other config file would reload the wrong thing on SIGUSR2.
+fn uses_config(pid: u32, config: &Path) -> bool {
+    let Ok(cmdline) = fs::read(format!("/proc/{}/cmdline", pid)) else { return false };
+    let args: Vec<&[u8]> = cmdline.split(|b| *b == 0).collect();
+    let wanted = config.as_os_str().as_encoded_bytes();
+    args.windows(2).any(|pair| (pair[0] == b"-c" || pair[0] == b"--config") && pair[1] == wanted)
+}
+
+/// Kills any running bar and starts a fresh one on `config`.
+pub fn restart(config: &Path) -> Result<Instance> {
+    // Kill existing instances to prevent duplicates or zombie processes.
+    // We ignore the result because it fails if Waybar isn't running, which is fine.
+    let _ = Command::new("pkill").arg("-x").arg("waybar").status();
+    // Brief sleep to ensure the socket is released by the OS before restarting.
+    thread::sleep(Duration::from_millis(500));
+
+    let log_path = log_path();
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let log = File::create(&log_path)
+        .with_context(|| format!("Failed to create waybar log file {:?}", log_path))?;
+    // Spawn new instance detached from this process
+    let child = Command::new("waybar")
+        .arg("-c")
+        .arg(config)
+        .stdout(Stdio::null())
+        .stderr(log)
+        .spawn()
+        .context("Failed to spawn new waybar process")?;
+    Ok(Instance::Spawned(child))
+}
+
+/// Reloads the running bar in place if it is already on `config`, otherwise restarts it.
+pub fn reload_or_restart(config: &Path) -> Result<Instance> {
+    let pids = running_pids();
+    if pids.is_empty() || !pids.iter().all(|pid| uses_config(*pid, config)) {
+        println!("Restarting Waybar...");
+        return restart(config);
+    }
+    println!("Reloading Waybar in place (SIGUSR2)...");
+    let status = Command::new("pkill")
+        .arg("-USR2")
+        .arg("-x")
+        .arg("waybar")
+        .status()
+        .context("Failed to run pkill")?;
+    if !status.success() {
+        // The bar vanished between pgrep and pkill; fall back to a clean start.
+        return restart(config);
+    }
+    Ok(Instance::Reloaded(pids))
+}
+
+/// Watches the bar for `grace`.
+/// Returns a description of what went wrong if it died during that window, `None` if it is still running.
+pub fn wait_for_crash(instance: &mut Instance, grace: Duration) -> Result<Option<String>> {
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        match instance {
+            Instance::Spawned(child) => {
+                if let Some(status) = child.try_wait()? {
+                    return Ok(Some(format!("Waybar exited ({})", status)));
+                }
+            }
+            Instance::Reloaded(pids) => {
+                if !pids.iter().any(|pid| is_alive(*pid)) {
+                    return Ok(Some("Waybar exited after reloading".to_string()));
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(None)
+}
+
+/// Last few lines Waybar wrote to stderr, for the failure notification.
+pub fn log_tail(lines: usize) -> String {
+    let log = fs::read_to_string(log_path()).unwrap_or_default();
+    let all: Vec<&str> = log.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
@@ -14,7 +14,7 @@ OFFICIAL_PACKAGES=(
 
 # 2. AUR Packages (From your Rust 'AUR_PACKAGES' const)
 AUR_PACKAGES=(
-  "wlogout"
+  "wlogout" "mpvpaper"
 )
 
 echo "🔍 Verifying OFFICIAL packages..."